@@ -30,6 +30,7 @@ use msgs::TestMessage;
 struct MyActor {
     cnt: usize,
     hb: bool,
+    world: Addr<Syn, World>,
     recipient: Recipient<Remote, TestMessage>,
 }
 
@@ -53,7 +54,17 @@ impl Actor for MyActor {
 
     fn started(&mut self, ctx: &mut Context<Self>) {
         if self.hb {
-            self.hb(ctx);
+            // start sending messages once remote node provides `TestMessage`
+            World::wait_for_provider::<TestMessage>(&self.world, Duration::from_secs(30))
+                .into_actor(self)
+                .then(|res, act, ctx| {
+                    match res {
+                        Ok(()) => act.hb(ctx),
+                        Err(err) => println!("NO PROVIDER: {}", err),
+                    }
+                    actix::fut::ok(())
+                })
+                .spawn(ctx);
         }
     }
 }
@@ -96,13 +107,13 @@ fn main() {
 
     let addr = world.start();
     let a: Addr<Unsync, _> = MyActor::create(move |ctx| {
-        ctx.run_later(Duration::from_millis(5000), move |_, ctx| {
-            // register actor as recipient for `TestMessage` message
+        // second instance registers actor as recipient for `TestMessage` message
+        if !hb {
             World::register_recipient(
                 &addr, ctx.address::<Addr<Syn, _>>().recipient());
-        });
+        }
 
-        MyActor{cnt: 0, hb, recipient}
+        MyActor{cnt: 0, hb, world: addr, recipient}
     });
 
     let _ = sys.run();
@@ -0,0 +1,432 @@
+//! Binary framing
+//!
+//! Every frame starts with fixed size header:
+//!
+//! | field          | size |
+//! |----------------|------|
+//! | frame type     | u8   |
+//! | message id     | u64  |
+//! | type id length | u16  |
+//! | version length | u16  |
+//! | payload format | u8   |
+//! | payload length | u32  |
+//! | deadline       | u32  |
+//!
+//! Deadline of message and notify frames is in milliseconds from now,
+//! zero if deadline is not set.
+//! Header is followed by type id, version and raw payload bytes.
+//! All integers are in network byte order. Frames without binary
+//! representation are sent as `control` frames with json payload.
+use std::{cmp, io, str};
+use serde::Serialize;
+use serde_json as json;
+use byteorder::{NetworkEndian, ByteOrder};
+use bytes::{BytesMut, BufMut};
+use tokio_io::codec::{Encoder, Decoder};
+
+use format::Format;
+use protocol::{Request, Response, frame_too_large};
+
+const HEADER_SIZE: usize = 22;
+
+const FRAME_CONTROL: u8 = 0;
+const FRAME_MESSAGE: u8 = 1;
+const FRAME_RESULT: u8 = 2;
+const FRAME_ERROR: u8 = 3;
+const FRAME_PING: u8 = 4;
+const FRAME_PONG: u8 = 5;
+const FRAME_CHUNK: u8 = 6;
+const FRAME_NOTIFY: u8 = 7;
+const FRAME_CANCEL: u8 = 8;
+
+
+struct Frame {
+    kind: u8,
+    id: u64,
+    type_id: String,
+    version: String,
+    format: Format,
+    payload: Vec<u8>,
+    deadline: u32,
+}
+
+impl Frame {
+    fn new(kind: u8, id: u64, payload: Vec<u8>) -> Frame {
+        Frame{kind: kind, id: id,
+              type_id: String::new(), version: String::new(),
+              format: Format::Json, payload: payload, deadline: 0}
+    }
+
+    fn message(kind: u8, id: u64, type_id: String, version: String,
+               format: Format, payload: Vec<u8>, deadline: Option<u64>) -> Frame {
+        // zero means no deadline, shortest deadline is one millisecond
+        let deadline = deadline
+            .map(|ms| cmp::max(cmp::min(ms, u64::from(u32::max_value())), 1) as u32)
+            .unwrap_or(0);
+        Frame{kind: kind, id: id, type_id: type_id, version: version,
+              format: format, payload: payload, deadline: deadline}
+    }
+
+    fn deadline(&self) -> Option<u64> {
+        if self.deadline == 0 {
+            None
+        } else {
+            Some(u64::from(self.deadline))
+        }
+    }
+
+    fn control<T: Serialize>(msg: &T) -> Result<Frame, io::Error> {
+        Ok(Frame::new(FRAME_CONTROL, 0, json::to_vec(msg)?))
+    }
+
+    fn error_code(&self) -> Result<u16, io::Error> {
+        if self.payload.len() != 2 {
+            return Err(invalid_data("Malformed error frame"))
+        }
+        Ok(NetworkEndian::read_u16(&self.payload))
+    }
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn to_string(buf: BytesMut) -> Result<String, io::Error> {
+    str::from_utf8(&buf)
+        .map(|s| s.to_owned())
+        .map_err(|_| invalid_data("Frame contains invalid utf-8"))
+}
+
+fn decode_frame(src: &mut BytesMut, max_size: usize) -> Result<Option<Frame>, io::Error> {
+    if src.len() < HEADER_SIZE {
+        return Ok(None)
+    }
+
+    let size = {
+        let buf: &[u8] = src.as_ref();
+        HEADER_SIZE +
+            NetworkEndian::read_u16(&buf[9..11]) as usize +
+            NetworkEndian::read_u16(&buf[11..13]) as usize +
+            NetworkEndian::read_u32(&buf[14..18]) as usize
+    };
+    if size > max_size {
+        return Err(frame_too_large())
+    }
+    if src.len() < size {
+        src.reserve(size);
+        return Ok(None)
+    }
+
+    let header = src.split_to(HEADER_SIZE);
+    let format = Format::from_id(header[13])
+        .ok_or_else(|| invalid_data("Unknown payload format"))?;
+    let type_id = to_string(src.split_to(NetworkEndian::read_u16(&header[9..11]) as usize))?;
+    let version = to_string(src.split_to(NetworkEndian::read_u16(&header[11..13]) as usize))?;
+    let payload = src.split_to(NetworkEndian::read_u32(&header[14..18]) as usize);
+
+    Ok(Some(Frame{kind: header[0],
+                  id: NetworkEndian::read_u64(&header[1..9]),
+                  type_id: type_id,
+                  version: version,
+                  format: format,
+                  payload: payload.to_vec(),
+                  deadline: NetworkEndian::read_u32(&header[18..22])}))
+}
+
+fn encode_frame(frame: Frame, dst: &mut BytesMut, max_size: usize) -> Result<(), io::Error> {
+    let size = HEADER_SIZE + frame.type_id.len() + frame.version.len() + frame.payload.len();
+    if size > max_size ||
+        frame.type_id.len() > u16::max_value() as usize ||
+        frame.version.len() > u16::max_value() as usize ||
+        frame.payload.len() > u32::max_value() as usize
+    {
+        return Err(frame_too_large())
+    }
+
+    dst.reserve(size);
+    dst.put_u8(frame.kind);
+    dst.put_u64::<NetworkEndian>(frame.id);
+    dst.put_u16::<NetworkEndian>(frame.type_id.len() as u16);
+    dst.put_u16::<NetworkEndian>(frame.version.len() as u16);
+    dst.put_u8(frame.format.id());
+    dst.put_u32::<NetworkEndian>(frame.payload.len() as u32);
+    dst.put_u32::<NetworkEndian>(frame.deadline);
+    dst.put(frame.type_id.as_bytes());
+    dst.put(frame.version.as_bytes());
+    dst.put(frame.payload.as_slice());
+    Ok(())
+}
+
+
+/// Binary codec for Client -> Server transport
+pub struct BinaryServerCodec {
+    max_frame_size: usize,
+}
+
+impl BinaryServerCodec {
+    pub fn new(max_frame_size: usize) -> BinaryServerCodec {
+        BinaryServerCodec{max_frame_size: max_frame_size}
+    }
+}
+
+impl Decoder for BinaryServerCodec
+{
+    type Item = Request;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        let frame = match decode_frame(src, self.max_frame_size)? {
+            Some(frame) => frame,
+            None => return Ok(None),
+        };
+
+        match frame.kind {
+            FRAME_MESSAGE => {
+                let deadline = frame.deadline();
+                Ok(Some(Request::Message(frame.id, frame.type_id, frame.version,
+                                         frame.format, frame.payload, deadline)))
+            },
+            FRAME_NOTIFY => {
+                let deadline = frame.deadline();
+                Ok(Some(Request::Notify(frame.type_id, frame.version,
+                                        frame.format, frame.payload, deadline)))
+            },
+            FRAME_CANCEL => Ok(Some(Request::Cancel(frame.id))),
+            FRAME_PING => Ok(Some(Request::Ping)),
+            FRAME_PONG => Ok(Some(Request::Pong)),
+            FRAME_CHUNK => Ok(Some(Request::Chunk(frame.payload))),
+            FRAME_CONTROL => Ok(Some(json::from_slice::<Request>(&frame.payload)?)),
+            _ => Err(invalid_data("Unknown frame type")),
+        }
+    }
+}
+
+impl Encoder for BinaryServerCodec
+{
+    type Item = Response;
+    type Error = io::Error;
+
+    fn encode(&mut self, msg: Response, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        let frame = match msg {
+            Response::Result(id, payload) => Frame::new(FRAME_RESULT, id, payload),
+            Response::Error(id, code) => {
+                let mut payload = vec![0; 2];
+                NetworkEndian::write_u16(&mut payload, code);
+                Frame::new(FRAME_ERROR, id, payload)
+            },
+            Response::Ping => Frame::new(FRAME_PING, 0, Vec::new()),
+            Response::Pong => Frame::new(FRAME_PONG, 0, Vec::new()),
+            Response::Chunk(payload) => Frame::new(FRAME_CHUNK, 0, payload),
+            msg => Frame::control(&msg)?,
+        };
+        encode_frame(frame, dst, self.max_frame_size)
+    }
+}
+
+
+/// Binary codec for Server -> Client transport
+pub struct BinaryClientCodec {
+    max_frame_size: usize,
+}
+
+impl BinaryClientCodec {
+    pub fn new(max_frame_size: usize) -> BinaryClientCodec {
+        BinaryClientCodec{max_frame_size: max_frame_size}
+    }
+}
+
+impl Decoder for BinaryClientCodec
+{
+    type Item = Response;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        let frame = match decode_frame(src, self.max_frame_size)? {
+            Some(frame) => frame,
+            None => return Ok(None),
+        };
+
+        match frame.kind {
+            FRAME_RESULT => Ok(Some(Response::Result(frame.id, frame.payload))),
+            FRAME_ERROR => Ok(Some(Response::Error(frame.id, frame.error_code()?))),
+            FRAME_PING => Ok(Some(Response::Ping)),
+            FRAME_PONG => Ok(Some(Response::Pong)),
+            FRAME_CHUNK => Ok(Some(Response::Chunk(frame.payload))),
+            FRAME_CONTROL => Ok(Some(json::from_slice::<Response>(&frame.payload)?)),
+            _ => Err(invalid_data("Unknown frame type")),
+        }
+    }
+}
+
+impl Encoder for BinaryClientCodec
+{
+    type Item = Request;
+    type Error = io::Error;
+
+    fn encode(&mut self, msg: Request, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        let frame = match msg {
+            Request::Message(id, type_id, version, format, payload, deadline) =>
+                Frame::message(FRAME_MESSAGE, id, type_id, version, format, payload, deadline),
+            Request::Notify(type_id, version, format, payload, deadline) =>
+                Frame::message(FRAME_NOTIFY, 0, type_id, version, format, payload, deadline),
+            Request::Cancel(id) => Frame::new(FRAME_CANCEL, id, Vec::new()),
+            Request::Ping => Frame::new(FRAME_PING, 0, Vec::new()),
+            Request::Pong => Frame::new(FRAME_PONG, 0, Vec::new()),
+            Request::Chunk(payload) => Frame::new(FRAME_CHUNK, 0, payload),
+            msg => Frame::control(&msg)?,
+        };
+        encode_frame(frame, dst, self.max_frame_size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAX_SIZE: usize = 1024;
+
+    fn encode_request(msg: Request) -> BytesMut {
+        let mut buf = BytesMut::new();
+        BinaryClientCodec::new(MAX_SIZE).encode(msg, &mut buf).unwrap();
+        buf
+    }
+
+    fn encode_response(msg: Response) -> BytesMut {
+        let mut buf = BytesMut::new();
+        BinaryServerCodec::new(MAX_SIZE).encode(msg, &mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn test_frame_roundtrip() {
+        let frame = Frame{kind: FRAME_MESSAGE, id: 10,
+                          type_id: "type".to_owned(), version: "1.0".to_owned(),
+                          format: Format::Json, payload: b"payload".to_vec(), deadline: 500};
+        let mut buf = BytesMut::new();
+        encode_frame(frame, &mut buf, MAX_SIZE).unwrap();
+        assert_eq!(buf.len(), HEADER_SIZE + 4 + 3 + 7);
+
+        let frame = decode_frame(&mut buf, MAX_SIZE).unwrap().unwrap();
+        assert_eq!(frame.kind, FRAME_MESSAGE);
+        assert_eq!(frame.id, 10);
+        assert_eq!(frame.type_id, "type");
+        assert_eq!(frame.version, "1.0");
+        assert_eq!(frame.format, Format::Json);
+        assert_eq!(frame.payload, b"payload".to_vec());
+        assert_eq!(frame.deadline, 500);
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_message_roundtrip() {
+        let mut buf = encode_request(Request::Message(
+            1, "type".to_owned(), "1.0".to_owned(), Format::Json, vec![1, 2, 3], Some(100)));
+        match BinaryServerCodec::new(MAX_SIZE).decode(&mut buf).unwrap() {
+            Some(Request::Message(id, type_id, ver, format, payload, deadline)) => {
+                assert_eq!(id, 1);
+                assert_eq!(type_id, "type");
+                assert_eq!(ver, "1.0");
+                assert_eq!(format, Format::Json);
+                assert_eq!(payload, vec![1, 2, 3]);
+                assert_eq!(deadline, Some(100));
+            },
+            msg => panic!("unexpected frame: {:?}", msg),
+        }
+    }
+
+    #[test]
+    fn test_notify_deadline() {
+        let mut buf = encode_request(Request::Notify(
+            "type".to_owned(), "1.0".to_owned(), Format::Json, Vec::new(), None));
+        buf.extend_from_slice(&encode_request(Request::Notify(
+            "type".to_owned(), "1.0".to_owned(), Format::Json, Vec::new(), Some(0))));
+
+        let mut codec = BinaryServerCodec::new(MAX_SIZE);
+        match codec.decode(&mut buf).unwrap() {
+            Some(Request::Notify(_, _, _, _, deadline)) => assert_eq!(deadline, None),
+            msg => panic!("unexpected frame: {:?}", msg),
+        }
+        // zero is reserved for missing deadline
+        match codec.decode(&mut buf).unwrap() {
+            Some(Request::Notify(_, _, _, _, deadline)) => assert_eq!(deadline, Some(1)),
+            msg => panic!("unexpected frame: {:?}", msg),
+        }
+    }
+
+    #[test]
+    fn test_response_roundtrip() {
+        let mut buf = encode_response(Response::Result(7, vec![4, 5]));
+        buf.extend_from_slice(&encode_response(Response::Error(8, 3)));
+        buf.extend_from_slice(&encode_response(Response::Supported(vec!["type".to_owned()])));
+
+        let mut codec = BinaryClientCodec::new(MAX_SIZE);
+        match codec.decode(&mut buf).unwrap() {
+            Some(Response::Result(7, payload)) => assert_eq!(payload, vec![4, 5]),
+            msg => panic!("unexpected frame: {:?}", msg),
+        }
+        match codec.decode(&mut buf).unwrap() {
+            Some(Response::Error(8, 3)) => (),
+            msg => panic!("unexpected frame: {:?}", msg),
+        }
+        match codec.decode(&mut buf).unwrap() {
+            Some(Response::Supported(types)) => assert_eq!(types, vec!["type".to_owned()]),
+            msg => panic!("unexpected frame: {:?}", msg),
+        }
+        assert!(codec.decode(&mut buf).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_partial_frame() {
+        let data = encode_response(Response::Result(1, vec![0; 100]));
+        let mut codec = BinaryClientCodec::new(MAX_SIZE);
+
+        let mut buf = BytesMut::from(&data[..HEADER_SIZE - 1]);
+        assert!(codec.decode(&mut buf).unwrap().is_none());
+        buf.extend_from_slice(&data[HEADER_SIZE - 1..HEADER_SIZE + 50]);
+        assert!(codec.decode(&mut buf).unwrap().is_none());
+        buf.extend_from_slice(&data[HEADER_SIZE + 50..]);
+        match codec.decode(&mut buf).unwrap() {
+            Some(Response::Result(1, payload)) => assert_eq!(payload.len(), 100),
+            msg => panic!("unexpected frame: {:?}", msg),
+        }
+    }
+
+    #[test]
+    fn test_frame_too_large() {
+        let mut buf = BytesMut::new();
+        let frame = Frame::new(FRAME_RESULT, 1, vec![0; MAX_SIZE]);
+        assert!(encode_frame(frame, &mut buf, MAX_SIZE).is_err());
+
+        let mut buf = encode_response(Response::Result(1, vec![0; 100]));
+        let err = decode_frame(&mut buf, 100).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_unknown_frame_type() {
+        let mut buf = BytesMut::new();
+        encode_frame(Frame::new(100, 1, Vec::new()), &mut buf, MAX_SIZE).unwrap();
+        let err = BinaryClientCodec::new(MAX_SIZE).decode(&mut buf).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        // result frames are not valid client requests
+        let mut buf = encode_response(Response::Result(1, Vec::new()));
+        assert!(BinaryServerCodec::new(MAX_SIZE).decode(&mut buf).is_err());
+    }
+
+    #[test]
+    fn test_malformed_error_frame() {
+        let mut buf = BytesMut::new();
+        encode_frame(Frame::new(FRAME_ERROR, 1, vec![1]), &mut buf, MAX_SIZE).unwrap();
+        let err = BinaryClientCodec::new(MAX_SIZE).decode(&mut buf).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_unknown_format() {
+        let mut buf = encode_request(Request::Message(
+            1, "type".to_owned(), "1.0".to_owned(), Format::Json, Vec::new(), None));
+        buf[13] = 255;
+        assert!(BinaryServerCodec::new(MAX_SIZE).decode(&mut buf).is_err());
+    }
+}
@@ -0,0 +1,119 @@
+use std::io;
+#[cfg(feature="deflate")]
+use std::io::{Read, Write};
+#[cfg(feature="deflate")]
+use flate2;
+
+
+/// Payload compression
+///
+/// `None` is always available, `Deflate` requires `deflate` crate feature.
+/// Compression is negotiated during handshake and applies to message
+/// and result payloads.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Compression {
+    None,
+    Deflate,
+}
+
+impl Default for Compression {
+    fn default() -> Compression {
+        Compression::None
+    }
+}
+
+impl Compression {
+    /// List of compression algorithms supported by this build
+    pub fn available() -> Vec<Compression> {
+        let mut algorithms = Vec::new();
+        #[cfg(feature="deflate")]
+        algorithms.push(Compression::Deflate);
+        algorithms.push(Compression::None);
+        algorithms
+    }
+
+    /// Check if compression algorithm is supported by this build
+    pub fn is_available(&self) -> bool {
+        Compression::available().contains(self)
+    }
+
+    /// List of algorithms to offer to remote peer, preferred algorithm goes first
+    pub(crate) fn offer(preferred: Compression) -> Vec<Compression> {
+        let mut algorithms = Compression::available();
+        if let Some(pos) = algorithms.iter().position(|c| *c == preferred) {
+            algorithms.remove(pos);
+            algorithms.insert(0, preferred);
+        }
+        algorithms
+    }
+
+    /// Select first offered algorithm that is supported by this build
+    pub(crate) fn negotiate(offered: &[Compression]) -> Compression {
+        offered.iter().find(|c| c.is_available()).cloned().unwrap_or_default()
+    }
+
+    pub(crate) fn compress(&self, data: Vec<u8>) -> Result<Vec<u8>, io::Error> {
+        match *self {
+            Compression::None => Ok(data),
+            Compression::Deflate => deflate(data),
+        }
+    }
+
+    /// Decompress payload, decompressed payload can not exceed `limit`
+    pub(crate) fn decompress(&self, data: Vec<u8>, limit: usize) -> Result<Vec<u8>, io::Error> {
+        match *self {
+            Compression::None => Ok(data),
+            Compression::Deflate => inflate(data, limit),
+        }
+    }
+}
+
+#[cfg(feature="deflate")]
+fn deflate(data: Vec<u8>) -> Result<Vec<u8>, io::Error> {
+    let mut encoder = flate2::write::DeflateEncoder::new(
+        Vec::with_capacity(data.len() / 2), flate2::Compression::fast());
+    encoder.write_all(&data)?;
+    encoder.finish()
+}
+
+#[cfg(feature="deflate")]
+fn inflate(data: Vec<u8>, limit: usize) -> Result<Vec<u8>, io::Error> {
+    let mut buf = Vec::new();
+    flate2::read::DeflateDecoder::new(data.as_slice())
+        .take(limit as u64 + 1)
+        .read_to_end(&mut buf)?;
+    if buf.len() > limit {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Payload is too large"))
+    }
+    Ok(buf)
+}
+
+#[cfg(not(feature="deflate"))]
+fn deflate(_: Vec<u8>) -> Result<Vec<u8>, io::Error> {
+    Err(io::Error::new(io::ErrorKind::Other, "Deflate compression is not available"))
+}
+
+#[cfg(not(feature="deflate"))]
+fn inflate(_: Vec<u8>, _: usize) -> Result<Vec<u8>, io::Error> {
+    Err(io::Error::new(io::ErrorKind::Other, "Deflate compression is not available"))
+}
+
+#[cfg(all(test, feature="deflate"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deflate_roundtrip() {
+        let data = vec![1; 1000];
+        let compressed = Compression::Deflate.compress(data.clone()).unwrap();
+        assert!(compressed.len() < data.len());
+        assert_eq!(Compression::Deflate.decompress(compressed, 1000).unwrap(), data);
+    }
+
+    #[test]
+    fn test_inflate_limit() {
+        let compressed = Compression::Deflate.compress(vec![1; 1000]).unwrap();
+        let err = Compression::Deflate.decompress(compressed, 999).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,98 @@
+use std::time::Duration;
+
+use format::Format;
+use compression::Compression;
+use protocol::Framing;
+use routing::{Dispatch, LocalPolicy};
+
+/// Default maximum frame size, 1Mb
+pub(crate) const DEFAULT_MAX_FRAME_SIZE: usize = 1024 * 1024;
+
+/// Default maximum size of reassembled message, 64Mb
+pub(crate) const DEFAULT_MAX_MESSAGE_SIZE: usize = 64 * 1024 * 1024;
+
+/// Default interval between heartbeat pings
+pub(crate) const DEFAULT_HEARTBEAT_INTERVAL: u64 = 5;
+
+/// Default number of unanswered pings before connection is considered dead
+pub(crate) const DEFAULT_HEARTBEAT_MISSES: usize = 3;
+
+/// Default capacity of per-node outbound queue
+pub(crate) const DEFAULT_QUEUE_CAPACITY: usize = 1024;
+
+/// Default time message can wait in outbound queue, in seconds
+pub(crate) const DEFAULT_QUEUE_TIMEOUT: u64 = 30;
+
+/// Default time caller waits for message result, in seconds
+pub(crate) const DEFAULT_REQUEST_TIMEOUT: u64 = 60;
+
+/// Smallest allowed frame size
+pub(crate) const MIN_FRAME_SIZE: usize = 4 * 1024;
+
+
+/// Outbound queue overflow policy
+///
+/// Messages are queued while connection to remote node is not established.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Overflow {
+    /// Reject new message
+    RejectNew,
+    /// Drop oldest queued message
+    DropOldest,
+}
+
+/// Network configuration shared by world, nodes and workers
+#[derive(Clone, Debug)]
+pub(crate) struct Config {
+    /// Preferred payload format
+    pub format: Format,
+    /// Preferred transport framing
+    pub framing: Framing,
+    /// Preferred payload compression
+    pub compression: Compression,
+    /// Maximum size of single frame
+    pub max_frame_size: usize,
+    /// Maximum size of message reassembled from chunks
+    pub max_message_size: usize,
+    /// Interval between heartbeat pings
+    pub heartbeat_interval: Duration,
+    /// Number of unanswered pings before connection is dropped
+    pub heartbeat_misses: usize,
+    /// Capacity of per-node outbound queue
+    pub queue_capacity: usize,
+    /// Outbound queue overflow policy
+    pub queue_overflow: Overflow,
+    /// Time message can wait in outbound queue
+    pub queue_timeout: Duration,
+    /// Delivery policy for types with local provider
+    pub local_policy: LocalPolicy,
+    /// Dispatch strategy for pools of local providers
+    pub dispatch: Dispatch,
+    /// Time caller waits for message result, unless message type
+    /// or request sets own timeout
+    pub request_timeout: Option<Duration>,
+    /// Capacity and timeout of recipient proxy queue for messages
+    /// sent before any provider is available
+    pub await_provider: Option<(usize, Duration)>,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            format: Format::Json,
+            framing: Framing::Binary,
+            compression: Compression::None,
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            heartbeat_interval: Duration::from_secs(DEFAULT_HEARTBEAT_INTERVAL),
+            heartbeat_misses: DEFAULT_HEARTBEAT_MISSES,
+            queue_capacity: DEFAULT_QUEUE_CAPACITY,
+            queue_overflow: Overflow::RejectNew,
+            queue_timeout: Duration::from_secs(DEFAULT_QUEUE_TIMEOUT),
+            local_policy: LocalPolicy::LocalPreferred,
+            dispatch: Dispatch::RoundRobin,
+            request_timeout: Some(Duration::from_secs(DEFAULT_REQUEST_TIMEOUT)),
+            await_provider: None,
+        }
+    }
+}
@@ -0,0 +1,118 @@
+use std::{error, fmt};
+
+use actix::MailboxError;
+
+use protocol;
+
+
+/// Remote message delivery error
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum RemoteError {
+    /// No connected node provides recipient for message type
+    NoProvider,
+    /// Message or result can not be serialized or deserialized locally
+    Serialization(String),
+    /// Remote node can not deserialize message
+    RemoteDeserialization,
+    /// Remote recipient failed to handle message
+    RemoteHandler,
+    /// Remote node rejected message, i.e. access denied or node is overloaded
+    RemoteRejected,
+    /// Remote node responded with unknown error code
+    Remote(u16),
+    /// Connection to remote node is not established
+    NotConnected,
+    /// Message can not be written to remote node connection
+    WriteFailed,
+    /// Connection to remote node has been lost before result is received
+    NodeDisconnected,
+    /// Message delivery timeout
+    Timeout,
+    /// Outbound queue of remote node is full
+    QueueFull,
+    /// Message or result payload exceeds message size limit of remote node
+    MessageTooLarge,
+    /// Recipient proxy is stopped
+    Closed,
+}
+
+impl RemoteError {
+    /// Convert `Response::Error` code to error
+    pub(crate) fn from_code(code: u16) -> RemoteError {
+        match code {
+            protocol::ERROR_NO_PROVIDER => RemoteError::NoProvider,
+            protocol::ERROR_DESERIALIZE => RemoteError::RemoteDeserialization,
+            protocol::ERROR_HANDLER => RemoteError::RemoteHandler,
+            protocol::ERROR_REJECTED => RemoteError::RemoteRejected,
+            protocol::ERROR_EXPIRED => RemoteError::Timeout,
+            protocol::ERROR_TOO_LARGE => RemoteError::MessageTooLarge,
+            code => RemoteError::Remote(code),
+        }
+    }
+}
+
+impl fmt::Display for RemoteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RemoteError::Serialization(ref err) => write!(f, "Serialization error: {}", err),
+            RemoteError::Remote(code) => write!(f, "Remote node error: {}", code),
+            _ => write!(f, "{}", error::Error::description(self)),
+        }
+    }
+}
+
+impl error::Error for RemoteError {
+    fn description(&self) -> &str {
+        match *self {
+            RemoteError::NoProvider => "No provider for message type",
+            RemoteError::Serialization(_) => "Serialization error",
+            RemoteError::RemoteDeserialization => "Remote node can not deserialize message",
+            RemoteError::RemoteHandler => "Remote recipient failed to handle message",
+            RemoteError::RemoteRejected => "Remote node rejected message",
+            RemoteError::Remote(_) => "Remote node error",
+            RemoteError::NotConnected => "Remote node is not connected",
+            RemoteError::WriteFailed => "Can not write message to remote node",
+            RemoteError::NodeDisconnected => "Remote node disconnected",
+            RemoteError::Timeout => "Message delivery timed out",
+            RemoteError::QueueFull => "Outbound queue is full",
+            RemoteError::MessageTooLarge => "Message is too large for remote node",
+            RemoteError::Closed => "Recipient proxy is closed",
+        }
+    }
+}
+
+impl From<MailboxError> for RemoteError {
+    fn from(err: MailboxError) -> RemoteError {
+        match err {
+            MailboxError::Timeout => RemoteError::Timeout,
+            MailboxError::Closed => RemoteError::Closed,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_code() {
+        assert_eq!(RemoteError::from_code(protocol::ERROR_NO_PROVIDER), RemoteError::NoProvider);
+        assert_eq!(RemoteError::from_code(protocol::ERROR_DESERIALIZE),
+                   RemoteError::RemoteDeserialization);
+        assert_eq!(RemoteError::from_code(protocol::ERROR_HANDLER), RemoteError::RemoteHandler);
+        assert_eq!(RemoteError::from_code(protocol::ERROR_REJECTED), RemoteError::RemoteRejected);
+        assert_eq!(RemoteError::from_code(protocol::ERROR_EXPIRED), RemoteError::Timeout);
+        assert_eq!(RemoteError::from_code(protocol::ERROR_TOO_LARGE),
+                   RemoteError::MessageTooLarge);
+        assert_eq!(RemoteError::from_code(0), RemoteError::Remote(0));
+        assert_eq!(RemoteError::from_code(500), RemoteError::Remote(500));
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(RemoteError::Remote(500).to_string(), "Remote node error: 500");
+        assert_eq!(RemoteError::Serialization("eof".to_owned()).to_string(),
+                   "Serialization error: eof");
+        assert_eq!(RemoteError::NoProvider.to_string(), "No provider for message type");
+    }
+}
@@ -0,0 +1,260 @@
+use std::fmt;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json;
+#[cfg(feature="bincode")]
+use bincode;
+#[cfg(feature="msgpack")]
+use rmp_serde;
+#[cfg(feature="cbor")]
+use serde_cbor;
+
+
+/// Serialization format of remote message payloads
+///
+/// `Json` is always available, other formats depend on enabled
+/// crate features (`bincode`, `msgpack`, `cbor`).
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Format {
+    Json,
+    Bincode,
+    MsgPack,
+    Cbor,
+}
+
+impl Default for Format {
+    fn default() -> Format {
+        Format::Json
+    }
+}
+
+impl Format {
+    /// List of formats supported by this build
+    pub fn available() -> Vec<Format> {
+        let mut formats = Vec::new();
+        #[cfg(feature="bincode")]
+        formats.push(Format::Bincode);
+        #[cfg(feature="msgpack")]
+        formats.push(Format::MsgPack);
+        #[cfg(feature="cbor")]
+        formats.push(Format::Cbor);
+        formats.push(Format::Json);
+        formats
+    }
+
+    /// Check if format is supported by this build
+    pub fn is_available(&self) -> bool {
+        Format::available().contains(self)
+    }
+
+    /// Wire identifier of the format
+    pub(crate) fn id(&self) -> u8 {
+        match *self {
+            Format::Json => 0,
+            Format::Bincode => 1,
+            Format::MsgPack => 2,
+            Format::Cbor => 3,
+        }
+    }
+
+    pub(crate) fn from_id(id: u8) -> Option<Format> {
+        match id {
+            0 => Some(Format::Json),
+            1 => Some(Format::Bincode),
+            2 => Some(Format::MsgPack),
+            3 => Some(Format::Cbor),
+            _ => None,
+        }
+    }
+
+    /// List of formats to offer to remote peer, preferred format goes first
+    pub(crate) fn offer(preferred: Format) -> Vec<Format> {
+        let mut formats = Format::available();
+        if let Some(pos) = formats.iter().position(|f| *f == preferred) {
+            formats.remove(pos);
+            formats.insert(0, preferred);
+        }
+        formats
+    }
+
+    /// Select first format from `offered` that is supported by remote peer
+    pub(crate) fn negotiate(offered: &[Format], remote: &[Format]) -> Format {
+        offered.iter().find(|f| remote.contains(f)).cloned().unwrap_or_default()
+    }
+
+    /// Serialize value
+    pub fn serialize<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, FormatError> {
+        match *self {
+            Format::Json => serde_json::to_vec(value).map_err(|e| FormatError::Serialize(e.to_string())),
+            Format::Bincode => bincode_to_vec(value),
+            Format::MsgPack => msgpack_to_vec(value),
+            Format::Cbor => cbor_to_vec(value),
+        }
+    }
+
+    /// Deserialize value
+    pub fn deserialize<T: DeserializeOwned>(&self, data: &[u8]) -> Result<T, FormatError> {
+        match *self {
+            Format::Json => serde_json::from_slice(data).map_err(|e| FormatError::Deserialize(e.to_string())),
+            Format::Bincode => bincode_from_slice(data),
+            Format::MsgPack => msgpack_from_slice(data),
+            Format::Cbor => cbor_from_slice(data),
+        }
+    }
+}
+
+/// Payload serialization error
+#[derive(Debug)]
+pub enum FormatError {
+    /// Format is not enabled in this build
+    Unavailable(Format),
+    Serialize(String),
+    Deserialize(String),
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FormatError::Unavailable(ref format) =>
+                write!(f, "Format is not available: {:?}", format),
+            FormatError::Serialize(ref err) => write!(f, "Serialization error: {}", err),
+            FormatError::Deserialize(ref err) => write!(f, "Deserialization error: {}", err),
+        }
+    }
+}
+
+#[cfg(feature="bincode")]
+fn bincode_to_vec<T: Serialize>(value: &T) -> Result<Vec<u8>, FormatError> {
+    bincode::serialize(value).map_err(|e| FormatError::Serialize(e.to_string()))
+}
+
+#[cfg(not(feature="bincode"))]
+fn bincode_to_vec<T: Serialize>(_: &T) -> Result<Vec<u8>, FormatError> {
+    Err(FormatError::Unavailable(Format::Bincode))
+}
+
+#[cfg(feature="bincode")]
+fn bincode_from_slice<T: DeserializeOwned>(data: &[u8]) -> Result<T, FormatError> {
+    bincode::deserialize(data).map_err(|e| FormatError::Deserialize(e.to_string()))
+}
+
+#[cfg(not(feature="bincode"))]
+fn bincode_from_slice<T: DeserializeOwned>(_: &[u8]) -> Result<T, FormatError> {
+    Err(FormatError::Unavailable(Format::Bincode))
+}
+
+#[cfg(feature="msgpack")]
+fn msgpack_to_vec<T: Serialize>(value: &T) -> Result<Vec<u8>, FormatError> {
+    rmp_serde::to_vec(value).map_err(|e| FormatError::Serialize(e.to_string()))
+}
+
+#[cfg(not(feature="msgpack"))]
+fn msgpack_to_vec<T: Serialize>(_: &T) -> Result<Vec<u8>, FormatError> {
+    Err(FormatError::Unavailable(Format::MsgPack))
+}
+
+#[cfg(feature="msgpack")]
+fn msgpack_from_slice<T: DeserializeOwned>(data: &[u8]) -> Result<T, FormatError> {
+    rmp_serde::from_slice(data).map_err(|e| FormatError::Deserialize(e.to_string()))
+}
+
+#[cfg(not(feature="msgpack"))]
+fn msgpack_from_slice<T: DeserializeOwned>(_: &[u8]) -> Result<T, FormatError> {
+    Err(FormatError::Unavailable(Format::MsgPack))
+}
+
+#[cfg(feature="cbor")]
+fn cbor_to_vec<T: Serialize>(value: &T) -> Result<Vec<u8>, FormatError> {
+    serde_cbor::to_vec(value).map_err(|e| FormatError::Serialize(e.to_string()))
+}
+
+#[cfg(not(feature="cbor"))]
+fn cbor_to_vec<T: Serialize>(_: &T) -> Result<Vec<u8>, FormatError> {
+    Err(FormatError::Unavailable(Format::Cbor))
+}
+
+#[cfg(feature="cbor")]
+fn cbor_from_slice<T: DeserializeOwned>(data: &[u8]) -> Result<T, FormatError> {
+    serde_cbor::from_slice(data).map_err(|e| FormatError::Deserialize(e.to_string()))
+}
+
+#[cfg(not(feature="cbor"))]
+fn cbor_from_slice<T: DeserializeOwned>(_: &[u8]) -> Result<T, FormatError> {
+    Err(FormatError::Unavailable(Format::Cbor))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct Msg {
+        id: u64,
+        name: String,
+        values: Vec<i32>,
+    }
+
+    fn msg() -> Msg {
+        Msg{id: 1, name: "msg".to_owned(), values: vec![-1, 0, 1]}
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        for format in Format::available() {
+            let data = format.serialize(&msg()).unwrap();
+            assert_eq!(format.deserialize::<Msg>(&data).unwrap(), msg());
+        }
+    }
+
+    #[test]
+    fn test_deserialize_error() {
+        for format in Format::available() {
+            match format.deserialize::<Msg>(&[0xff, 0xff]) {
+                Err(FormatError::Deserialize(_)) => (),
+                res => panic!("{:?}: unexpected result {:?}", format, res),
+            }
+        }
+    }
+
+    #[test]
+    fn test_unavailable() {
+        for format in &[Format::Bincode, Format::MsgPack, Format::Cbor] {
+            if format.is_available() {
+                continue
+            }
+            match format.serialize(&msg()) {
+                Err(FormatError::Unavailable(f)) => assert_eq!(f, *format),
+                res => panic!("{:?}: unexpected result {:?}", format, res),
+            }
+        }
+    }
+
+    #[test]
+    fn test_id() {
+        for format in &[Format::Json, Format::Bincode, Format::MsgPack, Format::Cbor] {
+            assert_eq!(Format::from_id(format.id()), Some(*format));
+        }
+        assert_eq!(Format::from_id(100), None);
+    }
+
+    #[test]
+    fn test_offer() {
+        let formats = Format::offer(Format::Json);
+        assert_eq!(formats[0], Format::Json);
+        assert_eq!(formats.len(), Format::available().len());
+
+        // unavailable format is not offered
+        let formats = Format::offer(Format::Cbor);
+        assert_eq!(formats.contains(&Format::Cbor), Format::Cbor.is_available());
+    }
+
+    #[test]
+    fn test_negotiate() {
+        let offered = [Format::Bincode, Format::MsgPack, Format::Json];
+        assert_eq!(Format::negotiate(&offered, &[Format::Json, Format::MsgPack]),
+                   Format::MsgPack);
+        assert_eq!(Format::negotiate(&offered, &[Format::Json]), Format::Json);
+        assert_eq!(Format::negotiate(&offered, &[Format::Cbor]), Format::Json);
+        assert_eq!(Format::negotiate(&[], &[Format::Bincode]), Format::Json);
+    }
+}
@@ -0,0 +1,196 @@
+//! `ACTIX/1.0` compatibility framing
+//!
+//! Nodes that announce `ACTIX/1.0` handshake marker speak protocol version 1:
+//! json envelopes prefixed with u16 length, message and result payloads are
+//! json strings. Version 1 does not support any of optional features,
+//! so connection always uses json format without compression and chunking.
+//! Server acknowledges handshake with bare handshake marker.
+use std::{cmp, io};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json as json;
+use byteorder::{NetworkEndian, ByteOrder};
+use bytes::{BytesMut, BufMut};
+use tokio_io::codec::{Encoder, Decoder};
+
+use format::Format;
+use protocol::{Request, Response, ClientHandshake, frame_too_large};
+
+/// Largest frame of version 1 protocol
+pub(crate) const MAX_FRAME_SIZE: usize = 0xffff;
+
+
+/// Version 1 client request
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag="cmd", content="data")]
+enum LegacyRequest {
+    Handshake(String),
+    Ping,
+    Pong,
+    /// Message(msg_id, type_id, ver, payload)
+    Message(u64, String, String, String),
+}
+
+/// Version 1 server response, handshake is sent as bare marker
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag="cmd", content="data")]
+enum LegacyResponse {
+    Ping,
+    Pong,
+    /// Announce supported message types
+    Supported(Vec<String>),
+    /// Response(msg_id, payload)
+    Result(u64, String),
+    /// Error(msg_id, error-code)
+    Error(u64, u16),
+}
+
+fn unsupported(frame: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput,
+                   format!("{} frame is not supported by ACTIX/1.0 protocol", frame))
+}
+
+fn to_string(payload: Vec<u8>) -> Result<String, io::Error> {
+    String::from_utf8(payload)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Payload is not valid utf-8"))
+}
+
+fn decode_frame<T: DeserializeOwned>(src: &mut BytesMut, max_size: usize)
+                                     -> Result<Option<T>, io::Error>
+{
+    let size = {
+        if src.len() < 2 {
+            return Ok(None)
+        }
+        NetworkEndian::read_u16(src.as_ref()) as usize
+    };
+    if size > max_size {
+        return Err(frame_too_large())
+    }
+
+    if src.len() >= size + 2 {
+        src.split_to(2);
+        let buf = src.split_to(size);
+        Ok(Some(json::from_slice::<T>(&buf)?))
+    } else {
+        Ok(None)
+    }
+}
+
+fn encode_frame<T: Serialize>(msg: &T, dst: &mut BytesMut, max_size: usize)
+                              -> Result<(), io::Error>
+{
+    let msg = json::to_vec(msg)?;
+    if msg.len() > max_size {
+        return Err(frame_too_large())
+    }
+
+    dst.reserve(msg.len() + 2);
+    dst.put_u16::<NetworkEndian>(msg.len() as u16);
+    dst.put(msg.as_slice());
+    Ok(())
+}
+
+/// Version 1 codec for Client -> Server transport
+pub struct LegacyServerCodec {
+    max_frame_size: usize,
+}
+
+impl LegacyServerCodec {
+    pub fn new(max_frame_size: usize) -> LegacyServerCodec {
+        LegacyServerCodec{max_frame_size: cmp::min(max_frame_size, MAX_FRAME_SIZE)}
+    }
+}
+
+impl Decoder for LegacyServerCodec
+{
+    type Item = Request;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        let msg = match decode_frame::<LegacyRequest>(src, self.max_frame_size)? {
+            Some(msg) => msg,
+            None => return Ok(None),
+        };
+        Ok(Some(match msg {
+            LegacyRequest::Handshake(addr) => Request::Handshake(ClientHandshake::legacy(addr)),
+            LegacyRequest::Ping => Request::Ping,
+            LegacyRequest::Pong => Request::Pong,
+            LegacyRequest::Message(id, type_id, ver, payload) =>
+                Request::Message(id, type_id, ver, Format::Json, payload.into_bytes(), None),
+        }))
+    }
+}
+
+impl Encoder for LegacyServerCodec
+{
+    type Item = Response;
+    type Error = io::Error;
+
+    fn encode(&mut self, msg: Response, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        let msg = match msg {
+            Response::Ping => LegacyResponse::Ping,
+            Response::Pong => LegacyResponse::Pong,
+            Response::Supported(types) => LegacyResponse::Supported(types),
+            Response::Result(id, payload) => LegacyResponse::Result(id, to_string(payload)?),
+            Response::Error(id, code) => LegacyResponse::Error(id, code),
+            Response::Handshake(_) | Response::HandshakeError(_) =>
+                return Err(unsupported("Handshake")),
+            Response::Unsupported(_) => return Err(unsupported("Unsupported")),
+            Response::Chunk(_) => return Err(unsupported("Chunk")),
+        };
+        encode_frame(&msg, dst, self.max_frame_size)
+    }
+}
+
+/// Version 1 codec for Server -> Client transport
+pub struct LegacyClientCodec {
+    max_frame_size: usize,
+}
+
+impl LegacyClientCodec {
+    pub fn new(max_frame_size: usize) -> LegacyClientCodec {
+        LegacyClientCodec{max_frame_size: cmp::min(max_frame_size, MAX_FRAME_SIZE)}
+    }
+}
+
+impl Decoder for LegacyClientCodec
+{
+    type Item = Response;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        let msg = match decode_frame::<LegacyResponse>(src, self.max_frame_size)? {
+            Some(msg) => msg,
+            None => return Ok(None),
+        };
+        Ok(Some(match msg {
+            LegacyResponse::Ping => Response::Ping,
+            LegacyResponse::Pong => Response::Pong,
+            LegacyResponse::Supported(types) => Response::Supported(types),
+            LegacyResponse::Result(id, payload) => Response::Result(id, payload.into_bytes()),
+            LegacyResponse::Error(id, code) => Response::Error(id, code),
+        }))
+    }
+}
+
+impl Encoder for LegacyClientCodec
+{
+    type Item = Request;
+    type Error = io::Error;
+
+    fn encode(&mut self, msg: Request, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        let msg = match msg {
+            Request::Handshake(hs) => LegacyRequest::Handshake(hs.addr),
+            Request::Ping => LegacyRequest::Ping,
+            Request::Pong => LegacyRequest::Pong,
+            Request::Message(id, type_id, ver, Format::Json, payload, _) =>
+                LegacyRequest::Message(id, type_id, ver, to_string(payload)?),
+            Request::Message(..) => return Err(unsupported("Non-json message")),
+            Request::Notify(..) => return Err(unsupported("Notify")),
+            Request::Cancel(_) => return Err(unsupported("Cancel")),
+            Request::Chunk(_) => return Err(unsupported("Chunk")),
+        };
+        encode_frame(&msg, dst, self.max_frame_size)
+    }
+}
@@ -8,17 +8,38 @@ extern crate serde_json;
 extern crate net2;
 #[macro_use] extern crate log;
 extern crate futures;
+extern crate rand;
 extern crate tokio_core;
 extern crate tokio_io;
+#[cfg(feature="bincode")] extern crate bincode;
+#[cfg(feature="msgpack")] extern crate rmp_serde;
+#[cfg(feature="cbor")] extern crate serde_cbor;
+#[cfg(feature="deflate")] extern crate flate2;
 
 mod msgs;
 mod node;
 mod world;
+mod error;
+mod format;
+mod compression;
 mod protocol;
+mod binary;
+mod legacy;
 mod remote;
 mod recipient;
+mod routing;
 mod worker;
 mod utils;
+mod config;
 
 pub use world::World;
-pub use remote::{Remote, RemoteMessage};
+pub use msgs::{AddNode, ClusterEvent, ClusterReady, ClusterState,
+               GetClusterState, RemoveNode, SubscribeCluster};
+pub use node::NodeStatus;
+pub use error::RemoteError;
+pub use config::Overflow;
+pub use remote::{DefaultTimeout, Remote, RemoteMessage, RemoteRecipientRequest};
+pub use format::{Format, FormatError};
+pub use compression::Compression;
+pub use protocol::Framing;
+pub use routing::{Dispatch, LocalPolicy, RoutingStrategy};
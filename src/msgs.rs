@@ -1,17 +1,22 @@
 #![allow(dead_code)]
 
-use std::{net, io};
+use std::net;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
 use std::sync::Arc;
 use serde::Serialize;
 use serde::de::DeserializeOwned;
+use futures::sync::oneshot;
 use futures::sync::mpsc::Receiver;
-use futures::unsync::oneshot::Sender;
 
-use actix::{Actor, Addr, Handler, Message, Unsync};
+use actix::{Actor, Addr, Handler, Message, Recipient, Syn, Unsync};
 
-use node::NetworkNode;
+use node::{NetworkNode, NodeInformation, NodeStatus};
+use error::RemoteError;
+use format::Format;
 use remote::RemoteMessage;
-use recipient::RemoteMessageHandler;
+use routing::RoutingStrategy;
+use recipient::{ProviderPool, RemoteMessageHandler};
 
 #[derive(Message)]
 pub(crate) struct RegisterNode {
@@ -21,8 +26,31 @@ pub(crate) struct RegisterNode {
 #[derive(Message)]
 pub(crate) struct ReconnectNode;
 
+/// World notifies NetworkNode.
+/// Node is removed, connection has to be closed.
 #[derive(Message)]
-pub(crate) struct NodeConnected(pub String);
+pub(crate) struct StopNode;
+
+/// NetworkWorker notifies world.
+/// Remote node connected to this world.
+#[derive(Message)]
+pub(crate) struct NodeConnected {
+    pub node: String,
+    pub worker: usize,
+}
+
+/// NetworkNode notifies world.
+/// Connection to remote node is established or lost.
+#[derive(Message)]
+pub(crate) struct NodeStatusChanged {
+    pub node: String,
+    pub status: NodeStatus,
+}
+
+/// NetworkNode notifies world.
+/// Node can not be reached, all its providers are gone.
+#[derive(Message)]
+pub(crate) struct NodeFailed(pub String);
 
 /// NetworkNode notifies world.
 /// New remote recipient is available.
@@ -32,6 +60,14 @@ pub(crate) struct NodeSupportedTypes {
     pub types: Vec<String>,
 }
 
+/// NetworkNode notifies world.
+/// Remote recipients are not available anymore.
+#[derive(Message)]
+pub(crate) struct NodeUnsupportedTypes {
+    pub node: String,
+    pub types: Vec<String>,
+}
+
 #[derive(Message)]
 pub(crate) struct WorkerDisconnected(pub usize);
 
@@ -41,6 +77,15 @@ pub struct ProvideRecipient{
     pub type_id: &'static str,
     pub handler: Arc<RemoteMessageHandler>}
 
+/// Unregister recipient provider
+///
+/// `stopped` is set if provider detected that local recipient is stopped.
+#[derive(Message, Clone)]
+pub(crate) struct WithdrawRecipient {
+    pub type_id: &'static str,
+    pub stopped: bool,
+}
+
 #[derive(Message)]
 pub(crate) struct GetRecipient<M>
     where M: RemoteMessage + 'static,
@@ -49,15 +94,32 @@ pub(crate) struct GetRecipient<M>
     pub rx: Receiver<M>,
 }
 
+/// World sends this message to RecipientProxy.
+/// Node does not provide specific type_id anymore.
 #[derive(Message)]
 pub(crate) struct NodeGone(pub String);
 
+/// World sends this message to RecipientProxy.
+/// Recipients are provided in same process.
+#[derive(Message)]
+pub(crate) struct LocalPool(pub Arc<ProviderPool>);
+
+/// World sends this message to RecipientProxy.
+/// Local recipient is unregistered.
+#[derive(Message)]
+pub(crate) struct LocalRecipientGone;
+
+/// Change routing strategy of recipient proxy
+#[derive(Message)]
+pub(crate) struct SetRouting(pub RoutingStrategy);
+
 /// World sends this message to RecipientProxy.
 /// Notifies about new node with support of specific type_id.
 #[derive(Message)]
 pub(crate) struct TypeSupported {
     pub type_id: String,
     pub node_id: String,
+    pub info: NodeInformation,
     pub node: Addr<Unsync, NetworkNode> }
 
 pub(crate) trait NodeOperations: Actor + Handler<NodeGone> + Handler<TypeSupported> {}
@@ -65,12 +127,105 @@ pub(crate) trait NodeOperations: Actor + Handler<NodeGone> + Handler<TypeSupport
 
 pub(crate) struct SendRemoteMessage{
     pub type_id: String,
-    pub data: String,
-    pub tx: Sender<String>,
+    pub format: Format,
+    pub data: Vec<u8>,
+    /// Request is cancelled when receiving side of this channel is dropped
+    pub cancel: Option<oneshot::Sender<()>>,
+    /// Result is not needed after deadline
+    pub deadline: Option<Instant>,
 }
 
+/// Result is serialized response of remote recipient
 impl Message for SendRemoteMessage {
-    type Result = Result<String, io::Error>;
+    type Result = Result<Vec<u8>, RemoteError>;
+}
+
+/// One-way message, result is not sent back
+#[derive(Message)]
+pub(crate) struct SendRemoteNotify {
+    pub type_id: String,
+    pub format: Format,
+    pub data: Vec<u8>,
+}
+
+/// Recipient proxy message for `Remote::do_send()`
+pub(crate) struct Notify<M>(pub M)
+    where M: RemoteMessage + 'static,
+          M::Result: Send + Serialize + DeserializeOwned;
+
+impl<M> Message for Notify<M>
+    where M: RemoteMessage + 'static,
+          M::Result: Send + Serialize + DeserializeOwned
+{
+    type Result = ();
+}
+
+/// Connect to network node while world is running
+#[derive(Message)]
+pub struct AddNode(pub String);
+
+/// Disconnect from network node while world is running
+///
+/// Providers of the node are removed. Node is not connected
+/// again, even if it connects to this world, until `AddNode` is sent.
+#[derive(Message)]
+pub struct RemoveNode(pub String);
+
+/// Wait until cluster is ready to handle message type
+///
+/// Resolves once at least `nodes` providers of `type_id` are available,
+/// fails with `RemoteError::Timeout` after `timeout`. Remote nodes count
+/// unless local policy is `LocalPolicy::LocalOnly`, local recipients count
+/// as one provider unless local policy is `LocalPolicy::RemoteOnly`.
+pub struct ClusterReady {
+    pub type_id: String,
+    pub nodes: usize,
+    pub timeout: Duration,
+}
+
+impl Message for ClusterReady {
+    type Result = Result<(), RemoteError>;
+}
+
+/// Membership and capability change of the cluster
+#[derive(Message, Clone, Debug)]
+pub enum ClusterEvent {
+    /// Network node is reachable
+    NodeUp(String),
+    /// Network node is not reachable anymore
+    NodeDown(String),
+    /// Network node provides recipient for message type
+    TypeAvailable { type_id: String, node: String },
+    /// Network node does not provide recipient for message type anymore
+    TypeWithdrawn { type_id: String, node: String },
+}
+
+/// Subscribe to cluster events
+///
+/// Subscriber receives `NodeUp` and `TypeAvailable` events
+/// for current state right after subscription.
+#[derive(Message)]
+pub struct SubscribeCluster(pub Recipient<Syn, ClusterEvent>);
+
+/// Get snapshot of world state
+pub struct GetClusterState;
+
+impl Message for GetClusterState {
+    type Result = ClusterState;
+}
+
+/// Snapshot of world state
+#[derive(Clone, Debug)]
+pub struct ClusterState {
+    /// Known network nodes and status of connection to them
+    pub nodes: HashMap<String, NodeStatus>,
+    /// Connected inbound workers and address of peer node,
+    /// address is known after handshake with peer is completed
+    pub workers: HashMap<usize, Option<String>>,
+    /// Remote nodes that provide message type
+    pub types: HashMap<String, Vec<String>>,
+    /// Message types with local recipients, and number of recipients in pool
+    pub handlers: HashMap<String, usize>,
 }
 
 //===================================
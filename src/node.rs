@@ -1,9 +1,11 @@
-use std::io;
-use std::cell::Cell;
+use std::{cmp, io};
+use std::cell::{Cell, RefCell};
 use std::sync::Arc;
-use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use std::collections::{HashMap, VecDeque};
 use backoff::ExponentialBackoff;
 use backoff::backoff::Backoff;
+use futures::{future, sync, Future};
 use futures::unsync::oneshot;
 use tokio_core::net::TcpStream;
 use tokio_io::AsyncRead;
@@ -14,9 +16,14 @@ use actix::prelude::{Response as ActixResponse};
 
 use msgs;
 use world::World;
-use protocol::{Request, Response, NetworkClientCodec};
+use error::RemoteError;
+use format::Format;
+use config::{Config, Overflow};
+use protocol::{self, Request, Response, ClientHandshake,
+               ChunkBuffer, CodecState, Framing, NetworkClientCodec};
 
 
+/// Status of connection to network node
 #[derive(Clone, Copy, PartialEq, Debug)]
 pub enum NodeStatus {
     New,
@@ -33,7 +40,9 @@ impl NodeInformation {
     pub fn new(addr: String) -> NodeInformation {
         NodeInformation{inner: Arc::new(
             Inner{addr: addr,
-                  status: Cell::new(NodeStatus::New)}
+                  status: Cell::new(NodeStatus::New),
+                  format: Cell::new(Format::Json),
+                  formats: RefCell::new(vec![Format::Json])}
         )}
     }
 
@@ -48,6 +57,25 @@ impl NodeInformation {
     pub fn set_status(&self, status: NodeStatus) {
         self.inner.as_ref().status.set(status)
     }
+
+    /// Payload format negotiated with remote node
+    pub fn format(&self) -> Format {
+        self.inner.as_ref().format.get()
+    }
+
+    /// Select payload format for message, use preferred format
+    /// if remote node supports it.
+    pub fn select_format(&self, preferred: Option<Format>) -> Format {
+        match preferred {
+            Some(format) if self.inner.as_ref().formats.borrow().contains(&format) => format,
+            _ => self.format(),
+        }
+    }
+
+    pub fn set_formats(&self, format: Format, formats: Vec<Format>) {
+        self.inner.as_ref().format.set(format);
+        *self.inner.as_ref().formats.borrow_mut() = formats;
+    }
 }
 
 impl Clone for NodeInformation {
@@ -59,6 +87,8 @@ impl Clone for NodeInformation {
 struct Inner {
     addr: String,
     status: Cell<NodeStatus>,
+    format: Cell<Format>,
+    formats: RefCell<Vec<Format>>,
 }
 
 /// NetworkNode - Actor responsible for network node
@@ -67,16 +97,49 @@ pub struct NetworkNode {
     world: Addr<Unsync, World>,
     addr: String,
     inner: NodeInformation,
+    config: Config,
+    chunk_size: usize,
+    max_payload: usize,
+    chunks: ChunkBuffer,
+    hb: Option<SpawnHandle>,
+    missed: usize,
     backoff: ExponentialBackoff,
     framed: Option<actix::io::FramedWrite<WriteHalf<TcpStream>, NetworkClientCodec>>,
-    requests: HashMap<u64, oneshot::Sender<String>>,
+    requests: HashMap<u64, oneshot::Sender<Result<Vec<u8>, RemoteError>>>,
+    queue: VecDeque<(Instant, Queued)>,
+    features: Vec<String>,
+    removed: bool,
+    legacy: bool,
+}
+
+/// Message waiting for connection to remote node
+///
+/// One-way messages do not have result channel.
+struct Queued {
+    msg: msgs::SendRemoteMessage,
+    tx: Option<oneshot::Sender<Result<Vec<u8>, RemoteError>>>,
+}
+
+impl Queued {
+    fn fail(self, err: RemoteError) {
+        if let Some(tx) = self.tx {
+            let _ = tx.send(Err(err));
+        }
+    }
 }
 
 impl Actor for NetworkNode {
     type Context = Context<Self>;
 
     fn started(&mut self, ctx: &mut Context<Self>) {
-        self.inner.set_status(NodeStatus::Connecting);
+        // node is removed from world, actor stops once all addresses are dropped
+        if self.removed {
+            return
+        }
+        self.set_status(NodeStatus::Connecting);
+
+        // drop expired messages from outbound queue
+        self.check_queue(ctx);
 
         // Connect to actix remote server
         actix::actors::Connector::from_registry()
@@ -87,18 +150,22 @@ impl Actor for NetworkNode {
                     info!("Connected to network node: {}", act.inner.address());
 
                     let (r, w) = stream.split();
+                    let state = CodecState::new(act.config.max_frame_size);
+                    if act.legacy {
+                        state.set_legacy();
+                    }
 
                     // configure write side of the connection
-                    let mut framed =
-                        actix::io::FramedWrite::new(w, NetworkClientCodec::default(), ctx);
-                    framed.write(Request::Handshake(act.addr.clone()));
+                    let mut framed = actix::io::FramedWrite::new(
+                        w, NetworkClientCodec::new(state.clone()), ctx);
+                    framed.write(Request::Handshake(
+                        ClientHandshake::new(act.addr.clone(), &act.config)));
                     act.framed = Some(framed);
 
                     // read side of the connection
-                    ctx.add_stream(FramedRead::new(r, NetworkClientCodec::default()));
+                    ctx.add_stream(FramedRead::new(r, NetworkClientCodec::new(state)));
 
                     act.backoff.reset();
-                    act.inner.set_status(NodeStatus::Ok);
                 },
                 Err(err) => act.restart(Some(err), ctx),
             })
@@ -109,25 +176,51 @@ impl Actor for NetworkNode {
 
 impl Supervised for NetworkNode {
     fn restarting(&mut self, _: &mut Self::Context) {
-        self.framed.take();
-        self.inner.set_status(NodeStatus::Failed);
-        //for tx in self.queue.drain(..) {
-        //let _ = tx.send(Err(Error::Disconnected));
-        //}
+        // remote node closed connection during handshake, `ACTIX/1.0` nodes
+        // reject newer handshake marker, next attempt uses other protocol
+        if self.framed.take().is_some() && self.inner.status() == NodeStatus::Connecting {
+            self.legacy = !self.legacy;
+            info!("Network node {} closed connection during handshake, \
+                   retrying with {} protocol", self.inner.address(),
+                  if self.legacy { "ACTIX/1.0" } else { "ACTIX/1.1" });
+        }
+        self.hb.take();
+        self.chunks = ChunkBuffer::new(self.config.max_message_size);
+        self.set_status(NodeStatus::Failed);
+        self.fail_requests();
     }
 }
 
-impl actix::io::WriteHandler<io::Error> for NetworkNode {}
+impl actix::io::WriteHandler<io::Error> for NetworkNode {
+    fn error(&mut self, err: io::Error, _: &mut Self::Context) -> Running {
+        error!("Can not write to network node {}: {}", self.inner.address(), err);
+        for (_, tx) in self.requests.drain() {
+            let _ = tx.send(Err(RemoteError::WriteFailed));
+        }
+        Running::Stop
+    }
+}
 
 impl NetworkNode {
-    pub fn new(addr: String, world: Addr<Unsync, World>, info: NodeInformation) -> NetworkNode {
+    pub fn new(addr: String, world: Addr<Unsync, World>,
+               info: NodeInformation, config: Config) -> NetworkNode {
         info!("New network node: {}", addr);
         NetworkNode {mid: 0,
                      world: world,
                      addr: addr,
                      inner: info,
+                     chunk_size: Framing::Json.chunk_size(config.max_frame_size),
+                     max_payload: usize::max_value(),
+                     chunks: ChunkBuffer::new(config.max_message_size),
+                     hb: None,
+                     missed: 0,
+                     config: config,
                      framed: None,
                      requests: HashMap::new(),
+                     queue: VecDeque::new(),
+                     features: Vec::new(),
+                     removed: false,
+                     legacy: false,
                      backoff: ExponentialBackoff::default(),
         }
     }
@@ -135,7 +228,11 @@ impl NetworkNode {
     pub fn restart(&mut self, err: Option<actix::actors::ConnectorError>, ctx: &mut Context<Self>)
     {
         self.framed.take();
-        self.inner.set_status(NodeStatus::Failed);
+        self.set_status(NodeStatus::Failed);
+        self.fail_requests();
+        if let Some(hb) = self.hb.take() {
+            ctx.cancel_future(hb);
+        }
 
         if let Some(err) = err {
             error!("Can not connect to network node: {}, err: {}",
@@ -149,10 +246,209 @@ impl NetworkNode {
         if let Some(timeout) = self.backoff.next_backoff() {
             ctx.run_later(timeout, |act, ctx| act.stop_actor(ctx));
         } else {
+            // node is considered dead, providers are removed until
+            // connection is established again
+            error!("Network node {} failed permanently", self.inner.address());
+            self.world.do_send(msgs::NodeFailed(self.inner.address().to_string()));
+            for (_, item) in self.queue.drain(..) {
+                item.fail(RemoteError::NodeDisconnected);
+            }
+            self.backoff.reset();
             self.stop_actor(ctx);
         }
     }
 
+    /// Send ping to remote node, restart connection if remote node
+    /// did not respond to previous pings
+    fn heartbeat(&mut self, ctx: &mut Context<Self>) {
+        if self.missed >= self.config.heartbeat_misses {
+            error!("Network node {} did not respond to {} pings",
+                   self.inner.address(), self.missed);
+            return self.restart(None, ctx)
+        }
+
+        if let Some(ref mut framed) = self.framed {
+            self.missed += 1;
+            framed.write(Request::Ping);
+        }
+        self.hb = Some(ctx.run_later(
+            self.config.heartbeat_interval, |act, ctx| act.heartbeat(ctx)));
+    }
+
+    /// Resolve all outstanding requests, connection to remote node is lost
+    fn fail_requests(&mut self) {
+        for (_, tx) in self.requests.drain() {
+            let _ = tx.send(Err(RemoteError::NodeDisconnected));
+        }
+    }
+
+    /// Queued message expires after queue timeout or message deadline
+    fn expires(&self, item: &Queued) -> Instant {
+        let expires = Instant::now() + self.config.queue_timeout;
+        match item.msg.deadline {
+            Some(deadline) if deadline < expires => deadline,
+            _ => expires,
+        }
+    }
+
+    /// Queue message until handshake with remote node completes
+    fn enqueue(&mut self, item: Queued) {
+        if self.removed {
+            return item.fail(RemoteError::NodeDisconnected)
+        }
+        self.expire_queue();
+
+        let expires = self.expires(&item);
+        if let Some(rejected) = push_bounded(
+            &mut self.queue, self.config.queue_capacity,
+            self.config.queue_overflow, expires, item)
+        {
+            warn!("Outbound queue for network node {} is full, message is dropped",
+                  self.inner.address());
+            rejected.fail(RemoteError::QueueFull);
+        }
+    }
+
+    /// Resolve queued messages that waited too long with timeout error
+    fn expire_queue(&mut self) {
+        let now = Instant::now();
+        if !self.queue.iter().any(|&(expires, _)| expires <= now) {
+            return
+        }
+        // messages with deadline could expire out of order
+        let (expired, queue): (VecDeque<_>, VecDeque<_>) =
+            self.queue.drain(..).partition(|&(expires, _)| expires <= now);
+        self.queue = queue;
+        for (_, item) in expired {
+            item.fail(RemoteError::Timeout);
+        }
+    }
+
+    /// Periodically drop expired messages from outbound queue
+    fn check_queue(&mut self, ctx: &mut Context<Self>) {
+        self.expire_queue();
+        ctx.run_later(Duration::from_secs(1), |act, ctx| act.check_queue(ctx));
+    }
+
+    /// Send queued messages, handshake with remote node is completed
+    fn flush_queue(&mut self, ctx: &mut Context<Self>) {
+        self.expire_queue();
+        if !self.queue.is_empty() {
+            debug!("Sending {} queued messages to network node {}",
+                   self.queue.len(), self.inner.address());
+        }
+        while let Some((_, item)) = self.queue.pop_front() {
+            self.write_message(item.msg, item.tx, ctx);
+        }
+    }
+
+    fn write_message(&mut self, msg: msgs::SendRemoteMessage,
+                     tx: Option<oneshot::Sender<Result<Vec<u8>, RemoteError>>>,
+                     ctx: &mut Context<Self>) {
+        let msgs::SendRemoteMessage{type_id, format, data, cancel, deadline} = msg;
+
+        // caller does not wait for result anymore
+        if cancel.as_ref().map(|c| c.is_canceled()).unwrap_or(false) {
+            return
+        }
+        let now = Instant::now();
+        if deadline.map(|d| d <= now).unwrap_or(false) {
+            if let Some(tx) = tx {
+                let _ = tx.send(Err(RemoteError::Timeout));
+            }
+            return
+        }
+        if self.framed.is_none() {
+            if let Some(tx) = tx {
+                let _ = tx.send(Err(RemoteError::NotConnected));
+            }
+            return
+        }
+        // remote node would drop connection on oversized message
+        if data.len() > self.max_payload {
+            warn!("Message {} payload size {} exceeds limit {} of network node {}",
+                  type_id, data.len(), self.max_payload, self.inner.address());
+            if let Some(tx) = tx {
+                let _ = tx.send(Err(RemoteError::MessageTooLarge));
+            }
+            return
+        }
+
+        // remote node abandons processing after deadline
+        let deadline = match deadline {
+            Some(deadline) if self.has_feature("deadline") => {
+                let remaining = deadline - now;
+                Some(remaining.as_secs() * 1000 + u64::from(remaining.subsec_nanos() / 1_000_000))
+            },
+            _ => None,
+        };
+
+        // large payloads are sent as sequence of chunks
+        let (chunks, data) = protocol::split_payload(data, self.chunk_size);
+
+        let request = if tx.is_none() && self.has_feature("notify") {
+            Request::Notify(type_id, "1.0".to_string(), format, data, deadline)
+        } else {
+            // result of one-way message is ignored if remote node
+            // does not support notifications
+            self.mid += 1;
+            if let Some(tx) = tx {
+                self.requests.insert(self.mid, tx);
+                if let Some(cancel) = cancel {
+                    let mid = self.mid;
+                    self.watch_cancel(mid, cancel, ctx);
+                }
+            }
+            Request::Message(self.mid, type_id, "1.0".to_string(), format, data, deadline)
+        };
+
+        if let Some(ref mut framed) = self.framed {
+            for chunk in chunks {
+                framed.write(Request::Chunk(chunk));
+            }
+            framed.write(request);
+        }
+    }
+
+    /// Cancel request when caller drops it
+    fn watch_cancel(&mut self, mid: u64, mut cancel: sync::oneshot::Sender<()>,
+                    ctx: &mut Context<Self>) {
+        ctx.spawn(
+            future::poll_fn(move || cancel.poll_cancel())
+                .into_actor(self)
+                .then(move |_, act, _| {
+                    act.cancel_request(mid);
+                    actix::fut::ok(())
+                }));
+    }
+
+    /// Drop request and notify remote node, result is not needed anymore
+    fn cancel_request(&mut self, mid: u64) {
+        if self.requests.remove(&mid).is_some() {
+            debug!("Request {} to network node {} is cancelled", mid, self.inner.address());
+            if self.has_feature("cancel") {
+                if let Some(ref mut framed) = self.framed {
+                    framed.write(Request::Cancel(mid));
+                }
+            }
+        }
+    }
+
+    fn has_feature(&self, feature: &str) -> bool {
+        self.features.iter().any(|f| f == feature)
+    }
+
+    /// Update connection status, world is notified when
+    /// connection is established or lost
+    fn set_status(&mut self, status: NodeStatus) {
+        let prev = self.inner.status();
+        self.inner.set_status(status);
+        if prev != status && (status == NodeStatus::Ok || prev == NodeStatus::Ok) {
+            self.world.do_send(msgs::NodeStatusChanged{
+                node: self.inner.address().to_string(), status: status});
+        }
+    }
+
     fn stop_actor(&mut self, ctx: &mut Context<Self>) {
         if self.inner.status() == NodeStatus::Failed {
             ctx.stop()
@@ -160,6 +456,26 @@ impl NetworkNode {
     }
 }
 
+/// Push item to bounded queue, returns item dropped according to overflow policy
+fn push_bounded<T>(queue: &mut VecDeque<(Instant, T)>, capacity: usize,
+                   overflow: Overflow, expires: Instant, item: T) -> Option<T>
+{
+    if queue.len() < capacity {
+        queue.push_back((expires, item));
+        return None
+    }
+    match overflow {
+        Overflow::DropOldest => match queue.pop_front() {
+            Some((_, oldest)) => {
+                queue.push_back((expires, item));
+                Some(oldest)
+            },
+            None => Some(item),
+        },
+        Overflow::RejectNew => Some(item),
+    }
+}
+
 impl StreamHandler<Response, io::Error> for NetworkNode
 {
     fn error(&mut self, err: io::Error, _ctx: &mut Self::Context) -> Running {
@@ -169,21 +485,82 @@ impl StreamHandler<Response, io::Error> for NetworkNode
     }
 
     /// This is main event loop for server responses
-    fn handle(&mut self, msg: Response, _ctx: &mut Self::Context) {
+    fn handle(&mut self, msg: Response, ctx: &mut Self::Context) {
+        // any frame from remote node proves connection is alive
+        self.missed = 0;
+
         match msg {
+            Response::Handshake(hs) => {
+                if let Err(err) = hs.validate() {
+                    error!("Handshake with network node {} failed: {}",
+                           self.inner.address(), err);
+                    return self.restart(None, ctx)
+                }
+
+                let format = Format::negotiate(&Format::offer(self.config.format), &hs.formats);
+                info!("Network node {} uses protocol version {}, {:?} framing, \
+                       {:?} format, {:?} compression, features: {:?}",
+                      self.inner.address(), hs.version, hs.framing,
+                      format, hs.compression, hs.features);
+
+                let frame_size = cmp::min(self.config.max_frame_size, hs.max_frame_size);
+                self.chunk_size = if hs.has_feature("chunking") {
+                    hs.framing.chunk_size(frame_size)
+                } else {
+                    usize::max_value()
+                };
+                self.max_payload = protocol::max_payload(
+                    hs.framing, hs.has_feature("chunking"), frame_size, hs.max_message_size);
+                if hs.has_feature("heartbeat") {
+                    self.hb = Some(ctx.run_later(
+                        self.config.heartbeat_interval, |act, ctx| act.heartbeat(ctx)));
+                }
+                self.inner.set_formats(format, hs.formats);
+                self.features = hs.features;
+
+                self.set_status(NodeStatus::Ok);
+                self.flush_queue(ctx);
+            },
+            Response::Ping => {
+                if let Some(ref mut framed) = self.framed {
+                    framed.write(Request::Pong);
+                }
+            },
+            Response::Pong => (),
+            Response::HandshakeError(err) => {
+                error!("Network node {} rejected handshake: {}", self.inner.address(), err);
+                self.restart(None, ctx)
+            },
+            Response::Chunk(chunk) => {
+                if let Err(err) = self.chunks.push(chunk) {
+                    error!("Network node {} error: {}", self.inner.address(), err);
+                    ctx.stop();
+                }
+            },
             Response::Supported(types) => {
                 self.world.do_send(msgs::NodeSupportedTypes {
                     node: self.inner.address().to_string(),
                     types: types
                 });
             },
+            Response::Unsupported(types) => {
+                self.world.do_send(msgs::NodeUnsupportedTypes {
+                    node: self.inner.address().to_string(),
+                    types: types
+                });
+            },
             Response::Result(id, data) => {
+                let data = self.chunks.complete(data);
+                if let Some(tx) = self.requests.remove(&id) {
+                    debug!("GOT REMOTE RESULT: {:?} {:?} bytes", id, data.len());
+                    let _ = tx.send(Ok(data));
+                }
+            },
+            Response::Error(id, code) => {
                 if let Some(tx) = self.requests.remove(&id) {
-                    debug!("GOT REMOTE RESULT: {:?} {:?}", id, data);
-                    let _ = tx.send(data);
+                    let _ = tx.send(Err(RemoteError::from_code(code)));
                 }
             },
-            _ => (),
         }
     }
 }
@@ -198,16 +575,106 @@ impl Handler<msgs::ReconnectNode> for NetworkNode {
 }
 
 
+/// Node is removed from world, close connection
+impl Handler<msgs::StopNode> for NetworkNode {
+    type Result = ();
+
+    fn handle(&mut self, _: msgs::StopNode, ctx: &mut Context<Self>) {
+        info!("Disconnecting from network node {}", self.inner.address());
+        self.removed = true;
+        self.framed.take();
+        self.set_status(NodeStatus::Failed);
+        self.fail_requests();
+        for (_, item) in self.queue.drain(..) {
+            item.fail(RemoteError::NodeDisconnected);
+        }
+        ctx.stop();
+    }
+}
+
 /// Send remote mesage
 impl Handler<msgs::SendRemoteMessage> for NetworkNode {
-    type Result = ActixResponse<String, io::Error>;
+    type Result = ActixResponse<Vec<u8>, RemoteError>;
 
-    fn handle(&mut self, msg: msgs::SendRemoteMessage, _: &mut Context<Self>) -> Self::Result {
-        if let Some(ref mut framed) = self.framed {
-            self.mid += 1;
-            self.requests.insert(self.mid, msg.tx);
-            framed.write(Request::Message(self.mid, msg.type_id, "1.0".to_string(), msg.data));
+    fn handle(&mut self, msg: msgs::SendRemoteMessage, ctx: &mut Context<Self>) -> Self::Result {
+        let (tx, rx) = oneshot::channel();
+        if self.inner.status() == NodeStatus::Ok {
+            self.write_message(msg, Some(tx), ctx);
+        } else {
+            self.enqueue(Queued{msg: msg, tx: Some(tx)});
         }
-        ActixResponse::reply(Err(io::Error::new(io::ErrorKind::Other, "test")))
+
+        ActixResponse::async(rx.then(|res| match res {
+            Ok(res) => res,
+            Err(_) => Err(RemoteError::NodeDisconnected),
+        }))
+    }
+}
+
+/// Send one-way message
+impl Handler<msgs::SendRemoteNotify> for NetworkNode {
+    type Result = ();
+
+    fn handle(&mut self, msg: msgs::SendRemoteNotify, ctx: &mut Context<Self>) {
+        let msg = msgs::SendRemoteMessage{
+            type_id: msg.type_id, format: msg.format, data: msg.data,
+            cancel: None, deadline: None};
+        if self.inner.status() == NodeStatus::Ok {
+            self.write_message(msg, None, ctx);
+        } else {
+            self.enqueue(Queued{msg: msg, tx: None});
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_select_format() {
+        let info = NodeInformation::new("127.0.0.1:8080".to_owned());
+        assert_eq!(info.select_format(None), Format::Json);
+        assert_eq!(info.select_format(Some(Format::Bincode)), Format::Json);
+
+        info.set_formats(Format::MsgPack, vec![Format::MsgPack, Format::Bincode, Format::Json]);
+        assert_eq!(info.select_format(None), Format::MsgPack);
+        assert_eq!(info.select_format(Some(Format::Bincode)), Format::Bincode);
+        assert_eq!(info.select_format(Some(Format::Json)), Format::Json);
+        assert_eq!(info.select_format(Some(Format::Cbor)), Format::MsgPack);
+    }
+
+    fn items(queue: &VecDeque<(Instant, u32)>) -> Vec<u32> {
+        queue.iter().map(|&(_, item)| item).collect()
+    }
+
+    #[test]
+    fn test_push_bounded_reject_new() {
+        let now = Instant::now();
+        let mut queue = VecDeque::new();
+        assert_eq!(push_bounded(&mut queue, 2, Overflow::RejectNew, now, 1), None);
+        assert_eq!(push_bounded(&mut queue, 2, Overflow::RejectNew, now, 2), None);
+        assert_eq!(push_bounded(&mut queue, 2, Overflow::RejectNew, now, 3), Some(3));
+        assert_eq!(items(&queue), vec![1, 2]);
+    }
+
+    #[test]
+    fn test_push_bounded_drop_oldest() {
+        let now = Instant::now();
+        let mut queue = VecDeque::new();
+        assert_eq!(push_bounded(&mut queue, 2, Overflow::DropOldest, now, 1), None);
+        assert_eq!(push_bounded(&mut queue, 2, Overflow::DropOldest, now, 2), None);
+        assert_eq!(push_bounded(&mut queue, 2, Overflow::DropOldest, now, 3), Some(1));
+        assert_eq!(push_bounded(&mut queue, 2, Overflow::DropOldest, now, 4), Some(2));
+        assert_eq!(items(&queue), vec![3, 4]);
+    }
+
+    #[test]
+    fn test_push_bounded_zero_capacity() {
+        let now = Instant::now();
+        let mut queue = VecDeque::new();
+        assert_eq!(push_bounded(&mut queue, 0, Overflow::DropOldest, now, 1), Some(1));
+        assert_eq!(push_bounded(&mut queue, 0, Overflow::RejectNew, now, 2), Some(2));
+        assert!(queue.is_empty());
     }
 }
@@ -1,46 +1,519 @@
-use std::io;
+use std::{cmp, io, mem};
+use std::rc::Rc;
+use std::cell::Cell;
+use serde::{Serialize, Deserialize, Deserializer};
+use serde::de::DeserializeOwned;
 use serde_json as json;
 use byteorder::{NetworkEndian , ByteOrder};
 use bytes::{BytesMut, BufMut};
 use tokio_io::codec::{Encoder, Decoder};
 
-const PREFIX: &[u8] = b"ACTIX/1.0\r\n";
+use format::Format;
+use compression::Compression;
+use config::{Config, DEFAULT_MAX_FRAME_SIZE, DEFAULT_MAX_MESSAGE_SIZE, MIN_FRAME_SIZE};
+use binary::{BinaryServerCodec, BinaryClientCodec};
+use legacy::{self, LegacyServerCodec, LegacyClientCodec};
 
+/// Handshake marker, protocol version is negotiated in handshake frame
+///
+/// Json framing of version 2 is not compatible with `ACTIX/1.0` nodes:
+/// frames are prefixed with u32 length instead of u16 and message payloads
+/// are byte arrays instead of json strings. Connections with `ACTIX/1.0`
+/// marker use protocol version 1, see `legacy` module. Outbound connection
+/// that is closed by remote node during handshake is retried with version 1,
+/// so clusters can be upgraded node by node.
+const PREFIX: &[u8] = b"ACTIX/1.1\r\n";
+const PREFIX_NAME: &[u8] = b"ACTIX/";
+const LEGACY_PREFIX: &[u8] = b"ACTIX/1.0\r\n";
+
+/// Highest supported protocol version
+pub(crate) const PROTOCOL_VERSION: u16 = 2;
+
+/// Lowest supported protocol version
+pub(crate) const MIN_PROTOCOL_VERSION: u16 = 1;
+
+/// Optional protocol features supported by this build
+pub(crate) const FEATURES: &[&str] = &[
+    "chunking", "heartbeat", "unsupported", "notify", "cancel", "deadline"];
+
+/// Space reserved in chunked frames for envelope and header fields
+const CHUNK_RESERVE: usize = 1024;
+
+/// Smallest payload size of single chunk
+const MIN_CHUNK_SIZE: usize = 256;
+
+
+/// Transport framing
+///
+/// Handshake is always sent with `Json` framing, after handshake
+/// both sides switch to framing selected by server.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Framing {
+    /// Length prefixed json envelopes
+    Json,
+    /// Binary frames with fixed header, see `binary` module
+    Binary,
+}
+
+impl Default for Framing {
+    fn default() -> Framing {
+        Framing::Json
+    }
+}
+
+impl Framing {
+    /// List of framings to offer to remote peer, preferred framing goes first
+    pub(crate) fn offer(preferred: Framing) -> Vec<Framing> {
+        match preferred {
+            Framing::Json => vec![Framing::Json, Framing::Binary],
+            Framing::Binary => vec![Framing::Binary, Framing::Json],
+        }
+    }
+
+    /// Select framing offered by client
+    pub(crate) fn negotiate(offered: &[Framing]) -> Framing {
+        offered.first().cloned().unwrap_or_default()
+    }
+
+    /// Maximum payload size of single frame for this framing,
+    /// json framing encodes every byte of payload as number.
+    pub(crate) fn chunk_size(&self, max_frame_size: usize) -> usize {
+        let size = max_frame_size.saturating_sub(CHUNK_RESERVE);
+        let size = match *self {
+            Framing::Json => size / 4,
+            Framing::Binary => size,
+        };
+        cmp::max(size, MIN_CHUNK_SIZE)
+    }
+}
+
+fn default_max_frame_size() -> usize {
+    DEFAULT_MAX_FRAME_SIZE
+}
+
+fn default_max_message_size() -> usize {
+    DEFAULT_MAX_MESSAGE_SIZE
+}
+
+/// Largest message payload that can be sent to remote node,
+/// without chunking whole payload has to fit into one frame
+pub(crate) fn max_payload(framing: Framing, chunking: bool,
+                          max_frame_size: usize, max_message_size: usize) -> usize {
+    if chunking {
+        max_message_size
+    } else {
+        cmp::min(framing.chunk_size(max_frame_size), max_message_size)
+    }
+}
+
+/// Deserialize list of capabilities, values unknown to this build are skipped
+fn known<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+    where D: Deserializer<'de>, T: DeserializeOwned
+{
+    let values = Vec::<json::Value>::deserialize(deserializer)?;
+    Ok(values.into_iter().filter_map(|v| json::from_value(v).ok()).collect())
+}
+
+/// Client handshake, lists client capabilities
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ClientHandshake {
+    /// Client node address
+    pub addr: String,
+    /// Supported protocol versions
+    pub versions: Vec<u16>,
+    /// Supported payload formats, preferred format goes first
+    #[serde(deserialize_with="known")]
+    pub formats: Vec<Format>,
+    /// Supported framings, preferred framing goes first
+    #[serde(default, deserialize_with="known")]
+    pub framings: Vec<Framing>,
+    /// Supported compression algorithms, preferred algorithm goes first
+    #[serde(default, deserialize_with="known")]
+    pub compression: Vec<Compression>,
+    /// Supported optional features
+    #[serde(default)]
+    pub features: Vec<String>,
+    /// Maximum frame size client accepts
+    #[serde(default="default_max_frame_size")]
+    pub max_frame_size: usize,
+    /// Maximum message payload size client accepts
+    #[serde(default="default_max_message_size")]
+    pub max_message_size: usize,
+}
+
+impl ClientHandshake {
+    pub(crate) fn new(addr: String, config: &Config) -> ClientHandshake {
+        ClientHandshake {
+            addr: addr,
+            versions: (MIN_PROTOCOL_VERSION..PROTOCOL_VERSION+1).collect(),
+            formats: Format::offer(config.format),
+            framings: Framing::offer(config.framing),
+            compression: Compression::offer(config.compression),
+            features: FEATURES.iter().map(|s| s.to_string()).collect(),
+            max_frame_size: config.max_frame_size,
+            max_message_size: config.max_message_size,
+        }
+    }
+
+    /// Capabilities of `ACTIX/1.0` node
+    pub(crate) fn legacy(addr: String) -> ClientHandshake {
+        ClientHandshake {
+            addr: addr,
+            versions: vec![1],
+            formats: vec![Format::Json],
+            framings: vec![Framing::Json],
+            compression: vec![Compression::None],
+            features: Vec::new(),
+            max_frame_size: legacy::MAX_FRAME_SIZE,
+            max_message_size: legacy::MAX_FRAME_SIZE,
+        }
+    }
+
+    /// Select highest common set of capabilities
+    pub(crate) fn negotiate(&self, config: &Config) -> Result<ServerHandshake, String> {
+        let version = self.versions.iter()
+            .filter(|v| **v >= MIN_PROTOCOL_VERSION && **v <= PROTOCOL_VERSION)
+            .max().cloned();
+        let version = match version {
+            Some(version) => version,
+            None => return Err(format!(
+                "No common protocol version, remote node supports {:?}, \
+                 local node supports {}-{}",
+                self.versions, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION)),
+        };
+        if self.max_frame_size < MIN_FRAME_SIZE {
+            return Err(format!("Remote node frame size {} is less than minimum {}",
+                               self.max_frame_size, MIN_FRAME_SIZE))
+        }
+
+        Ok(ServerHandshake {
+            version: version,
+            formats: Format::available(),
+            framing: Framing::negotiate(&self.framings),
+            compression: Compression::negotiate(&self.compression),
+            features: self.features.iter()
+                .filter(|f| FEATURES.contains(&f.as_str()))
+                .cloned().collect(),
+            max_frame_size: config.max_frame_size,
+            max_message_size: config.max_message_size,
+        })
+    }
+}
+
+/// Server handshake, contains selected capabilities
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ServerHandshake {
+    /// Selected protocol version
+    pub version: u16,
+    /// Supported payload formats
+    #[serde(deserialize_with="known")]
+    pub formats: Vec<Format>,
+    /// Selected framing
+    pub framing: Framing,
+    /// Selected compression algorithm
+    pub compression: Compression,
+    /// Optional features supported by both nodes
+    #[serde(default)]
+    pub features: Vec<String>,
+    /// Maximum frame size server accepts
+    #[serde(default="default_max_frame_size")]
+    pub max_frame_size: usize,
+    /// Maximum message payload size server accepts
+    #[serde(default="default_max_message_size")]
+    pub max_message_size: usize,
+}
+
+impl ServerHandshake {
+    /// Capabilities of `ACTIX/1.0` node, version 1 server sends bare handshake marker
+    pub(crate) fn legacy() -> ServerHandshake {
+        ServerHandshake {
+            version: 1,
+            formats: vec![Format::Json],
+            framing: Framing::Json,
+            compression: Compression::None,
+            features: Vec::new(),
+            max_frame_size: legacy::MAX_FRAME_SIZE,
+            max_message_size: legacy::MAX_FRAME_SIZE,
+        }
+    }
+
+    /// Check that server selected supported capabilities
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        if self.version < MIN_PROTOCOL_VERSION || self.version > PROTOCOL_VERSION {
+            Err(format!("Remote node selected unsupported protocol version {}, \
+                         local node supports {}-{}",
+                        self.version, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION))
+        } else if !self.compression.is_available() {
+            Err(format!("Remote node selected unsupported compression {:?}",
+                        self.compression))
+        } else if self.max_frame_size < MIN_FRAME_SIZE {
+            Err(format!("Remote node frame size {} is less than minimum {}",
+                        self.max_frame_size, MIN_FRAME_SIZE))
+        } else {
+            Ok(())
+        }
+    }
+
+    pub(crate) fn has_feature(&self, feature: &str) -> bool {
+        self.features.iter().any(|f| f == feature)
+    }
+}
 
 /// Client request
 #[derive(Serialize, Deserialize, Debug, Message)]
 #[serde(tag="cmd", content="data")]
 pub enum Request {
-    Handshake(String),
+    Handshake(ClientHandshake),
     Ping,
     Pong,
-    /// Message(msg_id, type_id, ver, payload)
-    Message(u64, String, String, String),
+    /// Message(msg_id, type_id, ver, format, payload, deadline),
+    /// deadline is in milliseconds from now
+    Message(u64, String, String, Format, Vec<u8>, Option<u64>),
+    /// One-way message, remote node does not send result
+    /// Notify(type_id, ver, format, payload, deadline)
+    Notify(String, String, Format, Vec<u8>, Option<u64>),
+    /// Result of message is not needed anymore
+    /// Cancel(msg_id)
+    Cancel(u64),
+    /// Part of large payload, payload of next message frame
+    /// is appended to the collected chunks
+    Chunk(Vec<u8>),
 }
 
+/// `Response::Error` code, remote node does not provide recipient for message type
+pub(crate) const ERROR_NO_PROVIDER: u16 = 1;
+/// `Response::Error` code, message payload can not be deserialized
+pub(crate) const ERROR_DESERIALIZE: u16 = 2;
+/// `Response::Error` code, recipient failed to handle message
+pub(crate) const ERROR_HANDLER: u16 = 3;
+/// `Response::Error` code, message is rejected, i.e. access denied or node is overloaded
+pub(crate) const ERROR_REJECTED: u16 = 4;
+/// `Response::Error` code, message deadline passed before result is ready
+pub(crate) const ERROR_EXPIRED: u16 = 5;
+/// `Response::Error` code, result payload exceeds message size limit of remote node
+pub(crate) const ERROR_TOO_LARGE: u16 = 6;
+
 /// Server response
 #[derive(Serialize, Deserialize, Debug, Message)]
 #[serde(tag="cmd", content="data")]
 pub enum Response {
-    Handshake,
+    Handshake(ServerHandshake),
     Ping,
     Pong,
     /// Announce supported message types
     Supported(Vec<String>),
-    /// Response(msg_id, payload)
-    Result(u64, String),
-    /// Error(msg_id, error-code)
+    /// Withdraw previously announced message types
+    Unsupported(Vec<String>),
+    /// Response(msg_id, payload), payload uses format of the request
+    Result(u64, Vec<u8>),
+    /// Error(msg_id, error-code), see `ERROR_*` codes
     Error(u64, u16),
+    /// Part of large payload, payload of next result frame
+    /// is appended to the collected chunks
+    Chunk(Vec<u8>),
+    /// Handshake failed, server closes connection
+    HandshakeError(String),
+}
+
+impl Request {
+    /// Apply function to payload of message, notify and chunk frames
+    fn map_payload<F>(self, f: F) -> Result<Request, io::Error>
+        where F: FnOnce(Vec<u8>) -> Result<Vec<u8>, io::Error>
+    {
+        match self {
+            Request::Message(id, type_id, ver, format, payload, deadline) =>
+                Ok(Request::Message(id, type_id, ver, format, f(payload)?, deadline)),
+            Request::Notify(type_id, ver, format, payload, deadline) =>
+                Ok(Request::Notify(type_id, ver, format, f(payload)?, deadline)),
+            Request::Chunk(payload) => Ok(Request::Chunk(f(payload)?)),
+            msg => Ok(msg),
+        }
+    }
+}
+
+impl Response {
+    /// Apply function to payload of result and chunk frames
+    fn map_payload<F>(self, f: F) -> Result<Response, io::Error>
+        where F: FnOnce(Vec<u8>) -> Result<Vec<u8>, io::Error>
+    {
+        match self {
+            Response::Result(id, payload) => Ok(Response::Result(id, f(payload)?)),
+            Response::Chunk(payload) => Ok(Response::Chunk(f(payload)?)),
+            msg => Ok(msg),
+        }
+    }
+}
+
+/// Split payload into chunks, returns chunks and last part of the payload
+pub(crate) fn split_payload(payload: Vec<u8>, size: usize) -> (Vec<Vec<u8>>, Vec<u8>) {
+    if payload.len() <= size {
+        return (Vec::new(), payload)
+    }
+    let mut chunks: Vec<Vec<u8>> = payload.chunks(size).map(|c| c.to_vec()).collect();
+    let last = chunks.pop().unwrap_or_default();
+    (chunks, last)
+}
+
+/// Collects chunks of large payload
+pub(crate) struct ChunkBuffer {
+    buf: Vec<u8>,
+    limit: usize,
+}
+
+impl ChunkBuffer {
+    pub fn new(limit: usize) -> ChunkBuffer {
+        ChunkBuffer{buf: Vec::new(), limit: limit}
+    }
+
+    /// Add chunk, fails if message exceeds size limit
+    pub fn push(&mut self, chunk: Vec<u8>) -> Result<(), io::Error> {
+        if self.buf.len() + chunk.len() > self.limit {
+            self.buf = Vec::new();
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Message is too large"))
+        }
+        self.buf.extend_from_slice(&chunk);
+        Ok(())
+    }
+
+    /// Complete payload with last part
+    pub fn complete(&mut self, last: Vec<u8>) -> Vec<u8> {
+        if self.buf.is_empty() {
+            last
+        } else {
+            let mut buf = mem::replace(&mut self.buf, Vec::new());
+            buf.extend_from_slice(&last);
+            buf
+        }
+    }
+}
+
+/// Connection state shared between read and write sides of the connection
+#[derive(Clone)]
+pub struct CodecState {
+    framing: Rc<Cell<Framing>>,
+    compression: Rc<Cell<Compression>>,
+    legacy: Rc<Cell<bool>>,
+    max_frame_size: usize,
+}
+
+impl CodecState {
+    pub fn new(max_frame_size: usize) -> CodecState {
+        CodecState{framing: Rc::new(Cell::new(Framing::Json)),
+                   compression: Rc::new(Cell::new(Compression::None)),
+                   legacy: Rc::new(Cell::new(false)),
+                   max_frame_size: max_frame_size}
+    }
+
+    pub fn framing(&self) -> Framing {
+        self.framing.get()
+    }
+
+    /// Connection uses `ACTIX/1.0` protocol
+    pub fn legacy(&self) -> bool {
+        self.legacy.get()
+    }
+
+    pub fn set_legacy(&self) {
+        self.legacy.set(true);
+    }
+
+    pub fn max_frame_size(&self) -> usize {
+        self.max_frame_size
+    }
+
+    /// Switch to capabilities selected during handshake
+    fn set_transport(&self, framing: Framing, compression: Compression) {
+        self.framing.set(framing);
+        self.compression.set(compression);
+    }
+
+    fn compress(&self, payload: Vec<u8>) -> Result<Vec<u8>, io::Error> {
+        self.compression.get().compress(payload)
+    }
+
+    fn decompress(&self, payload: Vec<u8>) -> Result<Vec<u8>, io::Error> {
+        self.compression.get().decompress(payload, self.max_frame_size)
+    }
+}
+
+/// Decode handshake marker, `ACTIX/1.0` marker switches connection to version 1
+fn decode_prefix(src: &mut BytesMut, state: &CodecState) -> Result<bool, io::Error> {
+    if src.len() < PREFIX.len() {
+        return Ok(false)
+    }
+    if &src[..PREFIX.len()] == PREFIX {
+        src.split_to(PREFIX.len());
+        Ok(true)
+    } else if &src[..LEGACY_PREFIX.len()] == LEGACY_PREFIX {
+        src.split_to(LEGACY_PREFIX.len());
+        state.set_legacy();
+        Ok(true)
+    } else if &src[..PREFIX_NAME.len()] == PREFIX_NAME {
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!("Unsupported handshake of remote node: {:?}, expected: {:?}",
+                    String::from_utf8_lossy(&src[..PREFIX.len()]),
+                    String::from_utf8_lossy(PREFIX))))
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::Other, "Remote peer is not an actix remote node"))
+    }
+}
+
+pub(crate) fn frame_too_large() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "Frame is too large")
+}
+
+fn decode_json<T: DeserializeOwned>(src: &mut BytesMut, max_size: usize)
+                                    -> Result<Option<T>, io::Error>
+{
+    let size = {
+        if src.len() < 4 {
+            return Ok(None)
+        }
+        NetworkEndian::read_u32(src.as_ref()) as usize
+    };
+    if size > max_size {
+        return Err(frame_too_large())
+    }
+
+    if src.len() >= size + 4 {
+        src.split_to(4);
+        let buf = src.split_to(size);
+        Ok(Some(json::from_slice::<T>(&buf)?))
+    } else {
+        src.reserve(size + 4);
+        Ok(None)
+    }
+}
+
+fn encode_json<T: Serialize>(msg: &T, dst: &mut BytesMut, max_size: usize)
+                             -> Result<(), io::Error>
+{
+    let msg = json::to_vec(msg)?;
+    if msg.len() > max_size {
+        return Err(frame_too_large())
+    }
+
+    dst.reserve(msg.len() + 4);
+    dst.put_u32::<NetworkEndian>(msg.len() as u32);
+    dst.put(msg.as_slice());
+    Ok(())
 }
 
 /// Codec for Client -> Server transport
 pub struct NetworkServerCodec {
-    prefix: bool
+    prefix: bool,
+    state: CodecState,
+    binary: BinaryServerCodec,
+    legacy: LegacyServerCodec,
 }
 
-impl Default for NetworkServerCodec {
-    fn default() -> NetworkServerCodec {
-        NetworkServerCodec{prefix: false}
+impl NetworkServerCodec {
+    pub fn new(state: CodecState) -> NetworkServerCodec {
+        let binary = BinaryServerCodec::new(state.max_frame_size());
+        let legacy = LegacyServerCodec::new(state.max_frame_size());
+        NetworkServerCodec{prefix: false, state: state, binary: binary, legacy: legacy}
     }
 }
 
@@ -51,30 +524,22 @@ impl Decoder for NetworkServerCodec
 
     fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
         if !self.prefix {
-            if src.len() < 11 {
+            if !decode_prefix(src, &self.state)? {
                 return Ok(None)
             }
-            if &src[..11] == PREFIX {
-                src.split_to(11);
-                self.prefix = true;
-            } else {
-                return Err(io::Error::new(io::ErrorKind::Other, "Prefix mismatch"))
-            }
+            self.prefix = true;
+        }
+        if self.state.legacy() {
+            return self.legacy.decode(src)
         }
-        
-        let size = {
-            if src.len() < 2 {
-                return Ok(None)
-            }
-            NetworkEndian::read_u16(src.as_ref()) as usize
-        };
 
-        if src.len() >= size + 2 {
-            src.split_to(2);
-            let buf = src.split_to(size);
-            Ok(Some(json::from_slice::<Request>(&buf)?))
-        } else {
-            Ok(None)
+        let msg = match self.state.framing() {
+            Framing::Json => decode_json::<Request>(src, self.state.max_frame_size())?,
+            Framing::Binary => self.binary.decode(src)?,
+        };
+        match msg {
+            Some(msg) => Ok(Some(msg.map_payload(|p| self.state.decompress(p))?)),
+            None => Ok(None),
         }
     }
 }
@@ -85,19 +550,39 @@ impl Encoder for NetworkServerCodec
     type Error = io::Error;
 
     fn encode(&mut self, msg: Response, dst: &mut BytesMut) -> Result<(), Self::Error> {
-        match msg {
-            Response::Handshake => dst.extend_from_slice(PREFIX),
-            _ => {
-                let msg = json::to_string(&msg).unwrap();
-                let msg_ref: &[u8] = msg.as_ref();
-
-                dst.reserve(msg_ref.len() + 2);
-                dst.put_u16::<NetworkEndian>(msg_ref.len() as u16);
-                dst.put(msg_ref);
+        // version 1 server acknowledges handshake with bare marker
+        // and can not report handshake errors
+        if self.state.legacy() {
+            return match msg {
+                Response::Handshake(_) => {
+                    dst.extend_from_slice(LEGACY_PREFIX);
+                    Ok(())
+                },
+                Response::HandshakeError(_) => Ok(()),
+                msg => self.legacy.encode(msg, dst),
             }
         }
 
-        Ok(())
+        // handshake is always json, switch to selected capabilities afterwards
+        match msg {
+            Response::Handshake(ref hs) => {
+                dst.extend_from_slice(PREFIX);
+                encode_json(&msg, dst, self.state.max_frame_size())?;
+                self.state.set_transport(hs.framing, hs.compression);
+                return Ok(())
+            },
+            Response::HandshakeError(_) => {
+                dst.extend_from_slice(PREFIX);
+                return encode_json(&msg, dst, self.state.max_frame_size())
+            },
+            _ => (),
+        }
+
+        let msg = msg.map_payload(|p| self.state.compress(p))?;
+        match self.state.framing() {
+            Framing::Json => encode_json(&msg, dst, self.state.max_frame_size()),
+            Framing::Binary => self.binary.encode(msg, dst),
+        }
     }
 }
 
@@ -105,11 +590,16 @@ impl Encoder for NetworkServerCodec
 /// Codec for Server -> Client transport
 pub struct NetworkClientCodec {
     prefix: bool,
+    state: CodecState,
+    binary: BinaryClientCodec,
+    legacy: LegacyClientCodec,
 }
 
-impl Default for NetworkClientCodec {
-    fn default() -> NetworkClientCodec {
-        NetworkClientCodec{prefix: false}
+impl NetworkClientCodec {
+    pub fn new(state: CodecState) -> NetworkClientCodec {
+        let binary = BinaryClientCodec::new(state.max_frame_size());
+        let legacy = LegacyClientCodec::new(state.max_frame_size());
+        NetworkClientCodec{prefix: false, state: state, binary: binary, legacy: legacy}
     }
 }
 
@@ -120,30 +610,35 @@ impl Decoder for NetworkClientCodec
 
     fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
         if !self.prefix {
-            if src.len() < 11 {
+            if !decode_prefix(src, &self.state)? {
                 return Ok(None)
             }
-            if &src[..11] == PREFIX {
-                src.split_to(11);
-                self.prefix = true;
-            } else {
-                return Err(io::Error::new(io::ErrorKind::Other, "Prefix mismatch"))
+            self.prefix = true;
+
+            // version 1 marker is the whole handshake
+            if self.state.legacy() {
+                return Ok(Some(Response::Handshake(ServerHandshake::legacy())))
             }
         }
+        if self.state.legacy() {
+            return self.legacy.decode(src)
+        }
 
-        let size = {
-            if src.len() < 2 {
-                return Ok(None)
-            }
-            NetworkEndian::read_u16(src.as_ref()) as usize
-        };
+        let msg = match self.state.framing() {
+            Framing::Json => {
+                let msg = decode_json::<Response>(src, self.state.max_frame_size())?;
 
-        if src.len() >= size + 2 {
-            src.split_to(2);
-            let buf = src.split_to(size);
-            Ok(Some(json::from_slice::<Response>(&buf)?))
-        } else {
-            Ok(None)
+                // server selected capabilities, following frames use them
+                if let Some(Response::Handshake(ref hs)) = msg {
+                    self.state.set_transport(hs.framing, hs.compression);
+                }
+                msg
+            },
+            Framing::Binary => self.binary.decode(src)?,
+        };
+        match msg {
+            Some(msg) => Ok(Some(msg.map_payload(|p| self.state.decompress(p))?)),
+            None => Ok(None),
         }
     }
 }
@@ -154,16 +649,282 @@ impl Encoder for NetworkClientCodec
     type Error = io::Error;
 
     fn encode(&mut self, msg: Request, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        if self.state.legacy() {
+            if let Request::Handshake(_) = msg {
+                dst.extend_from_slice(LEGACY_PREFIX);
+            }
+            return self.legacy.encode(msg, dst)
+        }
         if let Request::Handshake(_) = msg {
             dst.extend_from_slice(PREFIX);
+            return encode_json(&msg, dst, self.state.max_frame_size())
         }
 
-        let msg = json::to_string(&msg).unwrap();
-        let msg_ref: &[u8] = msg.as_ref();
+        let msg = msg.map_payload(|p| self.state.compress(p))?;
+        match self.state.framing() {
+            Framing::Json => encode_json(&msg, dst, self.state.max_frame_size()),
+            Framing::Binary => self.binary.encode(msg, dst),
+        }
+    }
+}
 
-        dst.reserve(msg_ref.len() + 2);
-        dst.put_u16::<NetworkEndian>(msg_ref.len() as u16);
-        dst.put(msg_ref);
-        Ok(())
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn handshake(config: &Config) -> ClientHandshake {
+        ClientHandshake::new("127.0.0.1:8080".to_owned(), config)
+    }
+
+    #[test]
+    fn test_split_payload() {
+        let (chunks, last) = split_payload(vec![1; 10], 10);
+        assert!(chunks.is_empty());
+        assert_eq!(last.len(), 10);
+
+        let payload: Vec<u8> = (0..25).collect();
+        let (chunks, last) = split_payload(payload.clone(), 10);
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(last.len(), 5);
+
+        let mut buf = ChunkBuffer::new(100);
+        for chunk in chunks {
+            buf.push(chunk).unwrap();
+        }
+        assert_eq!(buf.complete(last), payload);
+        // buffer is empty after completion
+        assert_eq!(buf.complete(vec![1]), vec![1]);
+    }
+
+    #[test]
+    fn test_chunk_buffer_limit() {
+        let mut buf = ChunkBuffer::new(10);
+        buf.push(vec![0; 6]).unwrap();
+        assert!(buf.push(vec![0; 6]).is_err());
+        // collected chunks are dropped
+        assert_eq!(buf.complete(vec![1]), vec![1]);
+    }
+
+    #[test]
+    fn test_chunk_size() {
+        assert_eq!(Framing::Binary.chunk_size(DEFAULT_MAX_FRAME_SIZE),
+                   DEFAULT_MAX_FRAME_SIZE - CHUNK_RESERVE);
+        assert!(Framing::Json.chunk_size(DEFAULT_MAX_FRAME_SIZE) <
+                Framing::Binary.chunk_size(DEFAULT_MAX_FRAME_SIZE));
+        assert_eq!(Framing::Binary.chunk_size(0), MIN_CHUNK_SIZE);
+        assert_eq!(Framing::Json.chunk_size(0), MIN_CHUNK_SIZE);
+    }
+
+    #[test]
+    fn test_max_payload() {
+        assert_eq!(max_payload(Framing::Binary, true, MIN_FRAME_SIZE, 1 << 20), 1 << 20);
+        assert_eq!(max_payload(Framing::Binary, false, MIN_FRAME_SIZE, 1 << 20),
+                   Framing::Binary.chunk_size(MIN_FRAME_SIZE));
+        assert_eq!(max_payload(Framing::Json, false, 1 << 20, 1024), 1024);
+    }
+
+    #[test]
+    fn test_negotiate() {
+        let config = Config::default();
+        let hs = handshake(&config).negotiate(&config).unwrap();
+        assert_eq!(hs.version, PROTOCOL_VERSION);
+        assert_eq!(hs.framing, config.framing);
+        assert_eq!(hs.compression, Compression::None);
+        assert_eq!(hs.features.len(), FEATURES.len());
+        assert!(hs.validate().is_ok());
+    }
+
+    #[test]
+    fn test_negotiate_unknown_features() {
+        let config = Config::default();
+        let mut client = handshake(&config);
+        client.features = vec!["heartbeat".to_owned(), "unknown".to_owned()];
+        let hs = client.negotiate(&config).unwrap();
+        assert_eq!(hs.features, vec!["heartbeat".to_owned()]);
+        assert!(hs.has_feature("heartbeat"));
+        assert!(!hs.has_feature("unknown"));
+    }
+
+    #[test]
+    fn test_negotiate_errors() {
+        let config = Config::default();
+
+        let mut client = handshake(&config);
+        client.versions = vec![PROTOCOL_VERSION + 1];
+        assert!(client.negotiate(&config).is_err());
+
+        let mut client = handshake(&config);
+        client.max_frame_size = MIN_FRAME_SIZE - 1;
+        assert!(client.negotiate(&config).is_err());
+    }
+
+    #[test]
+    fn test_validate_errors() {
+        let config = Config::default();
+
+        let mut hs = handshake(&config).negotiate(&config).unwrap();
+        hs.version = PROTOCOL_VERSION + 1;
+        assert!(hs.validate().is_err());
+
+        let mut hs = handshake(&config).negotiate(&config).unwrap();
+        hs.max_frame_size = MIN_FRAME_SIZE - 1;
+        assert!(hs.validate().is_err());
+    }
+
+    #[test]
+    fn test_handshake_skips_unknown_capabilities() {
+        let hs: ClientHandshake = json::from_str(
+            r#"{"addr": "127.0.0.1:8080", "versions": [2],
+                "formats": ["Json", "Unknown"], "framings": ["Unknown", "Binary"]}"#).unwrap();
+        assert_eq!(hs.formats, vec![Format::Json]);
+        assert_eq!(hs.framings, vec![Framing::Binary]);
+        assert_eq!(hs.max_frame_size, DEFAULT_MAX_FRAME_SIZE);
+        assert_eq!(hs.max_message_size, DEFAULT_MAX_MESSAGE_SIZE);
+    }
+
+    #[test]
+    fn test_json_roundtrip() {
+        let mut buf = BytesMut::new();
+        encode_json(&Response::Result(1, vec![1, 2]), &mut buf, 1024).unwrap();
+
+        let mut partial = buf.split_to(buf.len() - 1);
+        assert!(decode_json::<Response>(&mut partial, 1024).unwrap().is_none());
+        partial.extend_from_slice(&buf);
+        match decode_json::<Response>(&mut partial, 1024).unwrap() {
+            Some(Response::Result(1, payload)) => assert_eq!(payload, vec![1, 2]),
+            msg => panic!("unexpected frame: {:?}", msg),
+        }
+        assert!(partial.is_empty());
+    }
+
+    #[test]
+    fn test_json_frame_too_large() {
+        let mut buf = BytesMut::new();
+        assert!(encode_json(&Response::Result(1, vec![1; 100]), &mut buf, 10).is_err());
+
+        encode_json(&Response::Result(1, vec![1; 100]), &mut buf, 1024).unwrap();
+        assert!(decode_json::<Response>(&mut buf, 10).is_err());
+    }
+
+    #[test]
+    fn test_codec_handshake() {
+        let config = Config::default();
+        let mut client = NetworkClientCodec::new(CodecState::new(config.max_frame_size));
+        let mut server = NetworkServerCodec::new(CodecState::new(config.max_frame_size));
+
+        let mut buf = BytesMut::new();
+        client.encode(Request::Handshake(handshake(&config)), &mut buf).unwrap();
+        let hs = match server.decode(&mut buf).unwrap() {
+            Some(Request::Handshake(hs)) => hs.negotiate(&config).unwrap(),
+            msg => panic!("unexpected frame: {:?}", msg),
+        };
+        assert_eq!(hs.framing, Framing::Binary);
+
+        // both sides switch to binary framing after handshake
+        server.encode(Response::Handshake(hs), &mut buf).unwrap();
+        server.encode(Response::Result(1, vec![1, 2]), &mut buf).unwrap();
+        match client.decode(&mut buf).unwrap() {
+            Some(Response::Handshake(hs)) => assert_eq!(hs.framing, Framing::Binary),
+            msg => panic!("unexpected frame: {:?}", msg),
+        }
+        match client.decode(&mut buf).unwrap() {
+            Some(Response::Result(1, payload)) => assert_eq!(payload, vec![1, 2]),
+            msg => panic!("unexpected frame: {:?}", msg),
+        }
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_decode_prefix() {
+        let state = CodecState::new(DEFAULT_MAX_FRAME_SIZE);
+        let mut buf = BytesMut::from(&PREFIX[..5]);
+        assert!(!decode_prefix(&mut buf, &state).unwrap());
+        buf.extend_from_slice(&PREFIX[5..]);
+        assert!(decode_prefix(&mut buf, &state).unwrap());
+        assert!(buf.is_empty());
+        assert!(!state.legacy());
+
+        let mut buf = BytesMut::from(LEGACY_PREFIX);
+        assert!(decode_prefix(&mut buf, &state).unwrap());
+        assert!(buf.is_empty());
+        assert!(state.legacy());
+
+        let mut buf = BytesMut::from(&b"ACTIX/2.0\r\n"[..]);
+        assert!(decode_prefix(&mut buf, &state).is_err());
+
+        let mut buf = BytesMut::from(&b"GET / HTTP/1.1\r\n"[..]);
+        assert!(decode_prefix(&mut buf, &state).is_err());
+    }
+
+    #[test]
+    fn test_legacy_server() {
+        let config = Config::default();
+        let mut server = NetworkServerCodec::new(CodecState::new(config.max_frame_size));
+
+        // handshake and message as sent by ACTIX/1.0 node
+        let mut buf = BytesMut::from(LEGACY_PREFIX);
+        for frame in &[&br#"{"cmd":"Handshake","data":"127.0.0.1:8080"}"#[..],
+                       &br#"{"cmd":"Message","data":[1,"Type","1.0","{}"]}"#[..]] {
+            buf.reserve(frame.len() + 2);
+            buf.put_u16::<NetworkEndian>(frame.len() as u16);
+            buf.extend_from_slice(frame);
+        }
+        let hs = match server.decode(&mut buf).unwrap() {
+            Some(Request::Handshake(hs)) => hs.negotiate(&config).unwrap(),
+            msg => panic!("unexpected frame: {:?}", msg),
+        };
+        assert_eq!(hs.version, 1);
+        assert_eq!(hs.framing, Framing::Json);
+        assert!(hs.features.is_empty());
+        match server.decode(&mut buf).unwrap() {
+            Some(Request::Message(1, type_id, _, Format::Json, payload, None)) => {
+                assert_eq!(type_id, "Type");
+                assert_eq!(payload, b"{}".to_vec());
+            },
+            msg => panic!("unexpected frame: {:?}", msg),
+        }
+
+        server.encode(Response::Handshake(hs), &mut buf).unwrap();
+        assert_eq!(&buf[..], LEGACY_PREFIX);
+        buf.clear();
+        server.encode(Response::Result(1, b"{}".to_vec()), &mut buf).unwrap();
+        assert_eq!(&buf[2..], &br#"{"cmd":"Result","data":[1,"{}"]}"#[..]);
+        assert!(server.encode(Response::Chunk(vec![1]), &mut buf).is_err());
+    }
+
+    #[test]
+    fn test_legacy_client() {
+        let config = Config::default();
+        let state = CodecState::new(config.max_frame_size);
+        state.set_legacy();
+        let mut client = NetworkClientCodec::new(state);
+        let mut server = NetworkServerCodec::new(CodecState::new(config.max_frame_size));
+
+        let mut buf = BytesMut::new();
+        client.encode(Request::Handshake(handshake(&config)), &mut buf).unwrap();
+        assert!(buf.starts_with(LEGACY_PREFIX));
+        let hs = match server.decode(&mut buf).unwrap() {
+            Some(Request::Handshake(hs)) => hs.negotiate(&config).unwrap(),
+            msg => panic!("unexpected frame: {:?}", msg),
+        };
+
+        server.encode(Response::Handshake(hs), &mut buf).unwrap();
+        server.encode(Response::Error(1, ERROR_HANDLER), &mut buf).unwrap();
+        match client.decode(&mut buf).unwrap() {
+            Some(Response::Handshake(hs)) => {
+                assert_eq!(hs.version, 1);
+                assert!(hs.validate().is_ok());
+            },
+            msg => panic!("unexpected frame: {:?}", msg),
+        }
+        match client.decode(&mut buf).unwrap() {
+            Some(Response::Error(1, ERROR_HANDLER)) => (),
+            msg => panic!("unexpected frame: {:?}", msg),
+        }
+        assert!(buf.is_empty());
+
+        assert!(client.encode(
+            Request::Notify("Type".to_owned(), "1.0".to_owned(), Format::Json, vec![], None),
+            &mut buf).is_err());
     }
 }
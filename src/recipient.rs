@@ -1,22 +1,46 @@
 #![allow(dead_code, unused_variables)]
+use std::rc::Rc;
+use std::any::Any;
+use std::cell::Cell;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use std::collections::VecDeque;
 use std::marker::PhantomData;
-use std::collections::HashMap;
 
 use serde::Serialize;
 use serde::de::DeserializeOwned;
-use serde_json;
-use futures::Future;
-use futures::unsync::oneshot::{self, Sender};
+use futures::{future, Future};
+use futures::unsync;
+use futures::sync::oneshot;
 
 use actix::prelude::*;
-use actix::dev::{MessageResponse, ResponseChannel, SendError};
+use actix::MailboxError;
+use actix::dev::{MessageResponse, ResponseChannel, SendError, ToEnvelope};
 
 use msgs;
-use node::NetworkNode;
-use remote::{Remote, RemoteMessage};
+use protocol;
+use world::World;
+use error::RemoteError;
+use format::Format;
+use node::{NetworkNode, NodeInformation, NodeStatus};
+use routing::{Dispatch, HashRing, LocalPolicy, RoutingStrategy};
+use remote::{Remote, RemoteMessage, RemoteMessageEnvelope};
 
 pub trait RemoteMessageHandler: Send + Sync {
-    fn handle(&self, msg: String, sender: Sender<String>);
+    /// Handle serialized message, future resolves to serialized result
+    /// or `Response::Error` code. Result is not needed after `deadline`.
+    fn handle(&self, format: Format, msg: Vec<u8>,
+              deadline: Option<Instant>) -> Box<Future<Item=Vec<u8>, Error=u16>>;
+
+    /// Local recipient is stopped
+    fn closed(&self) -> bool {
+        false
+    }
+
+    /// Access concrete handler, recipient proxy uses it
+    /// to deliver messages without serialization
+    fn as_any(&self) -> &Any;
 }
 
 /// Remote message handler
@@ -25,25 +49,270 @@ struct Provider<M>
     where M: RemoteMessage + 'static,
           M::Result: Send + Serialize + DeserializeOwned
 {
-    pub recipient: Recipient<Syn, M>,
+    recipient: Recipient<Syn, M>,
+    world: Addr<Syn, World>,
+    closed: Arc<AtomicBool>,
+    /// Checks if recipient actor is alive, set for actor address providers
+    alive: Option<Box<Fn() -> bool + Send + Sync>>,
+}
+
+impl<M> Provider<M>
+    where M: RemoteMessage + 'static,
+          M::Result: Send + Serialize + DeserializeOwned
+{
+    pub fn new(recipient: Recipient<Syn, M>, world: Addr<Syn, World>) -> Self {
+        Provider{recipient: recipient, world: world,
+                 closed: Arc::new(AtomicBool::new(false)), alive: None}
+    }
+
+    /// Provider for actor address, stopped actor is detected
+    /// without delivering message to it
+    pub fn with_addr<A>(addr: Addr<Syn, A>, world: Addr<Syn, World>) -> Self
+        where A: Actor + Handler<M>, A::Context: ToEnvelope<Syn, A, M>
+    {
+        let alive = addr.clone();
+        Provider{recipient: addr.recipient(), world: world,
+                 closed: Arc::new(AtomicBool::new(false)),
+                 alive: Some(Box::new(move || alive.connected()))}
+    }
+
+    /// Deliver message to local recipient, no serialization is involved
+    fn send(&self, msg: M) -> Box<Future<Item=M::Result, Error=RemoteError>> {
+        let world = self.world.clone();
+        let closed = Arc::clone(&self.closed);
+        Box::new(self.recipient.send(msg).map_err(move |err| match err {
+            MailboxError::Closed => {
+                Provider::<M>::stopped(&closed, &world);
+                RemoteError::NoProvider
+            },
+            err => RemoteError::from(err),
+        }))
+    }
+
+    /// Deliver one-way message to local recipient
+    fn do_send(&self, msg: M) {
+        if self.recipient.do_send(msg).is_err() {
+            Provider::<M>::stopped(&self.closed, &self.world);
+        }
+    }
+
+    /// Local recipient is stopped, stop announcing it
+    fn stopped(closed: &AtomicBool, world: &Addr<Syn, World>) {
+        if !closed.swap(true, Ordering::Relaxed) {
+            warn!("Recipient of {} is stopped, unregistering", M::type_id());
+            world.do_send(msgs::WithdrawRecipient{type_id: M::type_id(), stopped: true});
+        }
+    }
 }
 
 impl<M> RemoteMessageHandler for Provider<M>
     where M: RemoteMessage + 'static, M::Result: Send + Serialize + DeserializeOwned
 {
-    fn handle(&self, msg: String, sender: Sender<String>) {
-        let msg = serde_json::from_slice::<M>(msg.as_ref()).unwrap();
-        Arbiter::handle().spawn(
-            self.recipient.send(msg).then(|res| {
-                match res {
-                    Ok(res) => {
-                        let body = serde_json::to_string(&res).unwrap();
-                        let _ = sender.send(body);
-                    },
-                    Err(e) => (),
-                }
-                Ok::<_, ()>(())
-            }))
+    fn handle(&self, format: Format, msg: Vec<u8>,
+              deadline: Option<Instant>) -> Box<Future<Item=Vec<u8>, Error=u16>> {
+        let mut msg = match format.deserialize::<M>(&msg) {
+            Ok(msg) => msg,
+            Err(err) => {
+                error!("Can not deserialize message {}: {}", M::type_id(), err);
+                return Box::new(future::err(protocol::ERROR_DESERIALIZE))
+            }
+        };
+        if let Some(deadline) = deadline {
+            if deadline <= Instant::now() {
+                debug!("Message {} is expired", M::type_id());
+                return Box::new(future::err(protocol::ERROR_EXPIRED))
+            }
+            msg.set_deadline(deadline);
+        }
+
+        let world = self.world.clone();
+        let closed = Arc::clone(&self.closed);
+        Box::new(self.recipient.send(msg).then(move |res| match res {
+            Ok(res) => format.serialize(&res).map_err(|err| {
+                error!("Can not serialize result of {}: {}", M::type_id(), err);
+                protocol::ERROR_HANDLER
+            }),
+            Err(MailboxError::Closed) => {
+                Provider::<M>::stopped(&closed, &world);
+                Err(protocol::ERROR_NO_PROVIDER)
+            },
+            Err(err) => {
+                error!("Recipient of {} failed: {}", M::type_id(), err);
+                Err(protocol::ERROR_HANDLER)
+            }
+        }))
+    }
+
+    fn closed(&self) -> bool {
+        self.closed.load(Ordering::Relaxed) ||
+            self.alive.as_ref().map(|alive| !alive()).unwrap_or(false)
+    }
+
+    fn as_any(&self) -> &Any {
+        self
+    }
+}
+
+/// Pool of local providers of same message type
+///
+/// Pool is immutable, world creates new pool on every registration change.
+/// Dispatch state is shared between pool generations.
+pub(crate) struct ProviderPool {
+    dispatch: Dispatch,
+    providers: Vec<(Arc<RemoteMessageHandler>, Arc<AtomicUsize>)>,
+    next: Arc<AtomicUsize>,
+}
+
+impl ProviderPool {
+    pub fn new(dispatch: Dispatch) -> ProviderPool {
+        ProviderPool{dispatch: dispatch, providers: Vec::new(), next: Arc::new(AtomicUsize::new(0))}
+    }
+
+    /// New pool with additional provider
+    pub fn with(&self, handler: Arc<RemoteMessageHandler>) -> ProviderPool {
+        let mut providers = self.providers.clone();
+        providers.push((handler, Arc::new(AtomicUsize::new(0))));
+        ProviderPool{dispatch: self.dispatch, providers: providers, next: Arc::clone(&self.next)}
+    }
+
+    /// New pool without stopped providers
+    pub fn without_closed(&self) -> ProviderPool {
+        ProviderPool{dispatch: self.dispatch,
+                     providers: self.providers.iter()
+                         .filter(|&&(ref handler, _)| !handler.closed())
+                         .cloned().collect(),
+                     next: Arc::clone(&self.next)}
+    }
+
+    pub fn len(&self) -> usize {
+        self.providers.len()
+    }
+
+    /// Check if any provider is stopped
+    pub fn has_closed(&self) -> bool {
+        self.providers.iter().any(|&(ref handler, _)| handler.closed())
+    }
+
+    /// Deliver message to local recipient selected by dispatch strategy
+    pub fn send_local<M>(&self, msg: M) -> Box<Future<Item=M::Result, Error=RemoteError>>
+        where M: RemoteMessage + 'static,
+              M::Result: Send + Serialize + DeserializeOwned
+    {
+        match self.acquire() {
+            Some((handler, busy)) => match handler.as_any().downcast_ref::<Provider<M>>() {
+                Some(provider) => Box::new(provider.send(msg).then(move |res| {
+                    drop(busy);
+                    res
+                })),
+                None => Box::new(future::err(RemoteError::NoProvider)),
+            },
+            None => Box::new(future::err(RemoteError::NoProvider)),
+        }
+    }
+
+    /// Deliver one-way message to local recipient selected by dispatch strategy
+    pub fn notify_local<M>(&self, msg: M) -> bool
+        where M: RemoteMessage + 'static,
+              M::Result: Send + Serialize + DeserializeOwned
+    {
+        let provider = self.select()
+            .and_then(|&(ref handler, _)| handler.as_any().downcast_ref::<Provider<M>>());
+        match provider {
+            Some(provider) => {
+                provider.do_send(msg);
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Select provider and count message in progress until guard is dropped
+    fn acquire(&self) -> Option<(&Arc<RemoteMessageHandler>, Busy)> {
+        self.select().map(|&(ref handler, ref busy)| {
+            busy.fetch_add(1, Ordering::Relaxed);
+            (handler, Busy(Arc::clone(busy)))
+        })
+    }
+
+    fn select(&self) -> Option<&(Arc<RemoteMessageHandler>, Arc<AtomicUsize>)> {
+        let candidates: Vec<_> = self.providers.iter()
+            .filter(|&&(ref handler, _)| !handler.closed())
+            .collect();
+        if candidates.is_empty() {
+            return None
+        }
+
+        match self.dispatch {
+            Dispatch::RoundRobin => {
+                let idx = self.next.fetch_add(1, Ordering::Relaxed);
+                Some(candidates[idx % candidates.len()])
+            },
+            Dispatch::LeastBusy => candidates.into_iter()
+                .min_by_key(|&&(_, ref busy)| busy.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+/// Decrements provider's number of messages in progress
+struct Busy(Arc<AtomicUsize>);
+
+impl Drop for Busy {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl RemoteMessageHandler for ProviderPool {
+    fn handle(&self, format: Format, msg: Vec<u8>,
+              deadline: Option<Instant>) -> Box<Future<Item=Vec<u8>, Error=u16>> {
+        match self.acquire() {
+            Some((handler, busy)) => {
+                Box::new(handler.handle(format, msg, deadline).then(move |res| {
+                    drop(busy);
+                    res
+                }))
+            },
+            None => Box::new(future::err(protocol::ERROR_NO_PROVIDER)),
+        }
+    }
+
+    fn closed(&self) -> bool {
+        self.providers.iter().all(|&(ref handler, _)| handler.closed())
+    }
+
+    fn as_any(&self) -> &Any {
+        self
+    }
+}
+
+/// Remote node that provides recipient
+struct ProxyNode {
+    id: String,
+    info: NodeInformation,
+    node: Addr<Unsync, NetworkNode>,
+    pending: Rc<Cell<usize>>,
+}
+
+/// Message waiting for provider
+struct Waiting<M>
+    where M: RemoteMessage + 'static,
+          M::Result: Send + Serialize + DeserializeOwned
+{
+    msg: M,
+    cancel: Option<oneshot::Sender<()>>,
+    deadline: Option<Instant>,
+    /// Not set for one-way messages
+    tx: Option<unsync::oneshot::Sender<Result<M::Result, RemoteError>>>,
+}
+
+impl<M> Waiting<M>
+    where M: RemoteMessage + 'static,
+          M::Result: Send + Serialize + DeserializeOwned
+{
+    fn fail(self, err: RemoteError) {
+        if let Some(tx) = self.tx {
+            let _ = tx.send(Err(err));
+        }
     }
 }
 
@@ -54,15 +323,265 @@ struct RecipientProxy<M>
           M::Result: Send + Serialize + DeserializeOwned
 {
     m: PhantomData<M>,
-    nodes: HashMap<String, Addr<Unsync, NetworkNode>>,
+    routing: RoutingStrategy,
+    policy: LocalPolicy,
+    local: Option<Arc<ProviderPool>>,
+    nodes: Vec<ProxyNode>,
+    ring: HashRing,
+    next: usize,
+    await_provider: Option<(usize, Duration)>,
+    waiting: VecDeque<(Instant, Waiting<M>)>,
+}
+
+impl<M> RecipientProxy<M>
+    where M: RemoteMessage + 'static,
+          M::Result: Send + Serialize + DeserializeOwned
+{
+    pub fn new(routing: RoutingStrategy, policy: LocalPolicy,
+               await_provider: Option<(usize, Duration)>) -> Self {
+        RecipientProxy{m: PhantomData, routing: routing, policy: policy, local: None,
+                       nodes: Vec::new(), ring: HashRing::default(), next: 0,
+                       await_provider: await_provider, waiting: VecDeque::new()}
+    }
+
+    /// Check if message could be delivered right now
+    fn has_provider(&self) -> bool {
+        match self.policy {
+            LocalPolicy::LocalOnly => self.has_local(),
+            LocalPolicy::LocalPreferred => self.has_local() || !self.nodes.is_empty(),
+            LocalPolicy::RemoteOnly => !self.nodes.is_empty(),
+        }
+    }
+
+    /// Check if message has to wait for provider
+    fn must_wait(&self) -> bool {
+        self.await_provider.is_some() && !self.has_provider()
+    }
+
+    /// Keep message until provider is available
+    fn wait(&mut self, item: Waiting<M>) {
+        let (capacity, timeout) = match self.await_provider {
+            Some(await_provider) => await_provider,
+            None => return item.fail(RemoteError::NoProvider),
+        };
+        self.expire_waiting();
+
+        if self.waiting.len() >= capacity {
+            warn!("Too many messages wait for provider of {}, message is dropped",
+                  M::type_id());
+            return item.fail(RemoteError::QueueFull)
+        }
+        let expires = Instant::now() + timeout;
+        let expires = match item.deadline {
+            Some(deadline) if deadline < expires => deadline,
+            _ => expires,
+        };
+        self.waiting.push_back((expires, item));
+    }
+
+    /// Resolve messages that waited too long with timeout error,
+    /// drop cancelled messages
+    fn expire_waiting(&mut self) {
+        let now = Instant::now();
+        let (expired, waiting): (VecDeque<_>, VecDeque<_>) = self.waiting.drain(..)
+            .partition(|&(expires, ref item)| {
+                expires <= now ||
+                    item.cancel.as_ref().map(|c| c.is_canceled()).unwrap_or(false)
+            });
+        self.waiting = waiting;
+        for (_, item) in expired {
+            item.fail(RemoteError::Timeout);
+        }
+    }
+
+    /// Periodically resolve expired waiting messages
+    fn check_waiting(&mut self, ctx: &mut Context<Self>) {
+        self.expire_waiting();
+        ctx.run_later(Duration::from_secs(1), |act, ctx| act.check_waiting(ctx));
+    }
+
+    /// Send waiting messages, provider is available
+    fn flush_waiting(&mut self) {
+        if self.waiting.is_empty() || !self.has_provider() {
+            return
+        }
+        self.expire_waiting();
+        debug!("Sending {} messages waiting for provider of {}",
+               self.waiting.len(), M::type_id());
+
+        let waiting: Vec<_> = self.waiting.drain(..).collect();
+        for (_, item) in waiting {
+            let Waiting{msg, cancel, deadline, tx} = item;
+            match tx {
+                Some(tx) => {
+                    let fut = self.dispatch(msg, cancel, deadline);
+                    Arbiter::handle().spawn(fut.then(move |res| {
+                        let _ = tx.send(res);
+                        Ok(())
+                    }));
+                },
+                None => self.notify(msg),
+            }
+        }
+    }
+
+    /// Check if running local recipient is registered,
+    /// remote nodes are used once all local recipients are stopped
+    fn has_local(&self) -> bool {
+        self.local.as_ref().map(|pool| !pool.closed()).unwrap_or(false)
+    }
+
+    /// Check if message has to be delivered to local recipient
+    fn use_local(&self) -> bool {
+        match self.policy {
+            LocalPolicy::LocalOnly => true,
+            LocalPolicy::LocalPreferred => self.has_local(),
+            LocalPolicy::RemoteOnly => false,
+        }
+    }
+
+    /// Deliver message to local recipient, no serialization is involved
+    fn send_local(&self, msg: M) -> Box<Future<Item=M::Result, Error=RemoteError>> {
+        match self.local {
+            Some(ref pool) => pool.send_local(msg),
+            None => Box::new(future::err(RemoteError::NoProvider)),
+        }
+    }
+
+    /// Select nodes for next message according to routing key or routing strategy
+    fn select(&mut self, key: Option<String>) -> Vec<usize> {
+        if let Some(key) = key {
+            return self.ring.get(&key)
+                .and_then(|id| self.nodes.iter().position(|node| node.id == id))
+                .into_iter().collect()
+        }
+
+        let nodes: Vec<_> = self.nodes.iter()
+            .map(|node| (node.info.status() == NodeStatus::Ok, node.pending.get()))
+            .collect();
+        self.routing.select(&nodes, &mut self.next)
+    }
+
+    /// Send message to specific node
+    fn send_to(&self, idx: usize, msg: &M,
+               cancel: Option<oneshot::Sender<()>>, deadline: Option<Instant>)
+               -> Box<Future<Item=M::Result, Error=RemoteError>>
+    {
+        let node = &self.nodes[idx];
+        let format = node.info.select_format(M::format());
+        let body = match format.serialize(msg) {
+            Ok(body) => body,
+            Err(err) => return Box::new(
+                future::err(RemoteError::Serialization(err.to_string()))),
+        };
+
+        let pending = Rc::clone(&node.pending);
+        pending.set(pending.get() + 1);
+
+        Box::new(
+            node.node.send(msgs::SendRemoteMessage{
+                type_id: M::type_id().to_string(), format: format, data: body,
+                cancel: cancel, deadline: deadline})
+                .then(move |res| {
+                    pending.set(pending.get() - 1);
+                    match res {
+                        Ok(Ok(body)) => format.deserialize::<M::Result>(&body).map_err(|err| {
+                            error!("Can not deserialize result of {}: {}", M::type_id(), err);
+                            RemoteError::Serialization(err.to_string())
+                        }),
+                        Ok(Err(err)) => Err(err),
+                        Err(err) => Err(RemoteError::from(err)),
+                    }
+                }))
+    }
 }
 
 impl<M> RecipientProxy<M>
     where M: RemoteMessage + 'static,
           M::Result: Send + Serialize + DeserializeOwned
 {
-    pub fn new() -> Self {
-        RecipientProxy{m: PhantomData, nodes: HashMap::new()}
+    /// Send one-way message to specific node
+    fn notify_to(&self, idx: usize, msg: &M) {
+        let node = &self.nodes[idx];
+        let format = node.info.select_format(M::format());
+        match format.serialize(msg) {
+            Ok(body) => node.node.do_send(msgs::SendRemoteNotify{
+                type_id: M::type_id().to_string(), format: format, data: body}),
+            Err(err) => error!("Can not serialize message {}: {}", M::type_id(), err),
+        }
+    }
+
+    /// Deliver message to local recipient or to selected nodes
+    fn dispatch(&mut self, mut msg: M, cancel: Option<oneshot::Sender<()>>,
+                deadline: Option<Instant>) -> Box<Future<Item=M::Result, Error=RemoteError>> {
+        if self.use_local() {
+            if let Some(deadline) = deadline {
+                msg.set_deadline(deadline);
+            }
+            return self.send_local(msg)
+        }
+
+        let nodes = self.select(msg.routing_key());
+        let mut futs: Vec<_> = if nodes.len() > 1 && cancel.is_some() {
+            // every node gets own cancellation channel,
+            // all of them are dropped when caller cancels request
+            let mut guards = Vec::new();
+            let futs: Vec<_> = nodes.into_iter()
+                .map(|idx| {
+                    let (tx, rx) = oneshot::channel();
+                    guards.push(rx);
+                    self.send_to(idx, &msg, Some(tx), deadline)
+                })
+                .collect();
+            if let Some(mut cancel) = cancel {
+                Arbiter::handle().spawn(
+                    future::poll_fn(move || cancel.poll_cancel()).then(move |_| {
+                        drop(guards);
+                        Ok(())
+                    }));
+            }
+            futs
+        } else {
+            let mut cancel = cancel;
+            nodes.into_iter()
+                .map(|idx| self.send_to(idx, &msg, cancel.take(), deadline))
+                .collect()
+        };
+
+        match futs.len() {
+            0 => Box::new(future::err(RemoteError::NoProvider)),
+            1 => futs.pop().unwrap(),
+            // broadcast, first successful result wins,
+            // remaining requests still have to complete
+            _ => Box::new(future::select_ok(futs).map(|(res, rest)| {
+                for fut in rest {
+                    Arbiter::handle().spawn(fut.then(|_| Ok(())));
+                }
+                res
+            })),
+        }
+    }
+
+    /// Deliver one-way message to local recipient or to selected nodes
+    fn notify(&mut self, msg: M) {
+        if self.use_local() {
+            let sent = match self.local {
+                Some(ref pool) => pool.notify_local(msg),
+                None => false,
+            };
+            if !sent {
+                warn!("No local provider for {}, message is dropped", M::type_id());
+            }
+            return
+        }
+
+        let nodes = self.select(msg.routing_key());
+        if nodes.is_empty() {
+            warn!("No provider for {}, message is dropped", M::type_id());
+        }
+        for idx in nodes {
+            self.notify_to(idx, &msg);
+        }
     }
 }
 
@@ -72,6 +591,12 @@ impl<M> Actor for RecipientProxy<M>
           M::Result: Send + Serialize + DeserializeOwned
 {
     type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        if self.await_provider.is_some() {
+            self.check_waiting(ctx);
+        }
+    }
 }
 
 impl<M> msgs::NodeOperations for RecipientProxy<M>
@@ -79,22 +604,41 @@ impl<M> msgs::NodeOperations for RecipientProxy<M>
           M::Result: Send + Serialize + DeserializeOwned {}
 
 /// Handler for proxied message
-impl<M> Handler<M> for RecipientProxy<M>
+impl<M> Handler<RemoteMessageEnvelope<M>> for RecipientProxy<M>
     where M: RemoteMessage + 'static,
           M::Result: Send + Serialize + DeserializeOwned
 {
     type Result = RecipientProxyResult<M>;
 
-    fn handle(&mut self, msg: M, ctx: &mut Context<Self>) -> RecipientProxyResult<M> {
-        let (tx, rx) = oneshot::channel();
-        let body = serde_json::to_string(&msg).unwrap();
+    fn handle(&mut self, msg: RemoteMessageEnvelope<M>,
+              ctx: &mut Context<Self>) -> RecipientProxyResult<M> {
+        let (msg, cancel, deadline) = msg.into_parts();
+
+        if self.must_wait() {
+            let (tx, rx) = unsync::oneshot::channel();
+            self.wait(Waiting{msg: msg, cancel: cancel, deadline: deadline, tx: Some(tx)});
+            return RecipientProxyResult{m: PhantomData, fut: Box::new(rx.then(|res| match res {
+                Ok(res) => res,
+                Err(_) => Err(RemoteError::Closed),
+            }))}
+        }
+        RecipientProxyResult{m: PhantomData, fut: self.dispatch(msg, cancel, deadline)}
+    }
+}
+
+/// Handler for one-way message, result is not expected
+impl<M> Handler<msgs::Notify<M>> for RecipientProxy<M>
+    where M: RemoteMessage + 'static,
+          M::Result: Send + Serialize + DeserializeOwned
+{
+    type Result = ();
 
-        for node in self.nodes.values() {
-            node.do_send(msgs::SendRemoteMessage{
-                type_id: M::type_id().to_string(), data: body, tx: tx});
-            break
+    fn handle(&mut self, msg: msgs::Notify<M>, ctx: &mut Context<Self>) {
+        if self.must_wait() {
+            self.wait(Waiting{msg: msg.0, cancel: None, deadline: None, tx: None});
+        } else {
+            self.notify(msg.0)
         }
-        RecipientProxyResult{m: PhantomData, rx: rx}
     }
 }
 
@@ -109,10 +653,21 @@ impl<M> Handler<msgs::TypeSupported> for RecipientProxy<M>
 
     fn handle(&mut self, msg: msgs::TypeSupported, ctx: &mut Context<Self>) {
         debug!("Remote provider {} is registerd for {}", msg.node_id, msg.type_id);
-        self.nodes.insert(msg.node_id, msg.node);
+        if let Some(node) = self.nodes.iter_mut().find(|node| node.id == msg.node_id) {
+            node.info = msg.info;
+            node.node = msg.node;
+            return
+        }
+        self.ring.add(&msg.node_id);
+        self.nodes.push(ProxyNode{id: msg.node_id, info: msg.info, node: msg.node,
+                                  pending: Rc::new(Cell::new(0))});
+        self.flush_waiting();
     }
 }
 
+/// Handle notification from World, node does not provide type anymore.
+///
+/// Messages are not routed to this node.
 impl<M> Handler<msgs::NodeGone> for RecipientProxy<M>
     where M: RemoteMessage + 'static,
           M::Result: Send + Serialize + DeserializeOwned
@@ -120,7 +675,47 @@ impl<M> Handler<msgs::NodeGone> for RecipientProxy<M>
     type Result = ();
 
     fn handle(&mut self, msg: msgs::NodeGone, ctx: &mut Context<Self>) {
-        unimplemented!()
+        debug!("Remote provider {} is gone for {}", msg.0, M::type_id());
+        self.ring.remove(&msg.0);
+        self.nodes.retain(|node| node.id != msg.0);
+    }
+}
+
+/// Pool of local recipients is registered or updated
+impl<M> Handler<msgs::LocalPool> for RecipientProxy<M>
+    where M: RemoteMessage + 'static,
+          M::Result: Send + Serialize + DeserializeOwned
+{
+    type Result = ();
+
+    fn handle(&mut self, msg: msgs::LocalPool, ctx: &mut Context<Self>) {
+        debug!("Local provider is registerd for {}", M::type_id());
+        self.local = Some(msg.0);
+        self.flush_waiting();
+    }
+}
+
+/// Local recipient is unregistered
+impl<M> Handler<msgs::LocalRecipientGone> for RecipientProxy<M>
+    where M: RemoteMessage + 'static,
+          M::Result: Send + Serialize + DeserializeOwned
+{
+    type Result = ();
+
+    fn handle(&mut self, msg: msgs::LocalRecipientGone, ctx: &mut Context<Self>) {
+        self.local = None;
+    }
+}
+
+/// Change routing strategy
+impl<M> Handler<msgs::SetRouting> for RecipientProxy<M>
+    where M: RemoteMessage + 'static,
+          M::Result: Send + Serialize + DeserializeOwned
+{
+    type Result = ();
+
+    fn handle(&mut self, msg: msgs::SetRouting, ctx: &mut Context<Self>) {
+        self.routing = msg.0;
     }
 }
 
@@ -130,24 +725,23 @@ pub struct RecipientProxyResult<M>
           M::Result: Send + Serialize + DeserializeOwned
 {
     m: PhantomData<M>,
-    rx: oneshot::Receiver<String>,
+    fut: Box<Future<Item=M::Result, Error=RemoteError>>,
 }
 
-impl<M> MessageResponse<RecipientProxy<M>, M> for RecipientProxyResult<M>
+impl<M> MessageResponse<RecipientProxy<M>, RemoteMessageEnvelope<M>> for RecipientProxyResult<M>
     where M: RemoteMessage + 'static,
           M::Result: Send + Serialize + DeserializeOwned
 {
-    fn handle<R: ResponseChannel<M>>(self, _: &mut Context<RecipientProxy<M>>, tx: Option<R>) {
+    fn handle<R>(self, _: &mut Context<RecipientProxy<M>>, tx: Option<R>)
+        where R: ResponseChannel<RemoteMessageEnvelope<M>>
+    {
         Arbiter::handle().spawn(
-            self.rx
-                .map_err(|e| ())
-                .and_then(move |msg| {
-                    let msg = serde_json::from_slice::<M::Result>(msg.as_ref()).unwrap();
-                    if let Some(tx) = tx {
-                        let _ = tx.send(msg);
-                    }
-                    Ok(())
-                })
+            self.fut.then(move |res| {
+                if let Some(tx) = tx {
+                    tx.send(res);
+                }
+                Ok(())
+            })
         );
     }
 }
@@ -159,6 +753,7 @@ pub struct RecipientProxySender<M>
 {
     m: PhantomData<M>,
     tx: Addr<Syn, RecipientProxy<M>>,
+    timeout: Option<Duration>,
 }
 
 use remote::RemoteRecipientRequest;
@@ -167,21 +762,27 @@ impl<M> RecipientProxySender<M>
     where M: RemoteMessage,
           M::Result: Send + Serialize + DeserializeOwned
 {
-    pub(crate) fn new(addr: Addr<Syn, RecipientProxy<M>>) -> RecipientProxySender<M> {
-        RecipientProxySender{m: PhantomData, tx: addr}
+    pub(crate) fn new(addr: Addr<Syn, RecipientProxy<M>>,
+                      timeout: Option<Duration>) -> RecipientProxySender<M> {
+        RecipientProxySender{m: PhantomData, tx: addr, timeout: timeout}
     }
 
+    /// Send one-way message, remote node does not send result back
     pub fn do_send(&self, msg: M) -> Result<(), SendError<M>> {
-        self.tx.do_send(msg);
+        self.tx.do_send(msgs::Notify(msg));
         Ok(())
     }
 
     pub fn try_send(&self, msg: M) -> Result<(), SendError<M>> {
-        self.tx.try_send(msg)
+        match self.tx.try_send(RemoteMessageEnvelope::from(msg)) {
+            Ok(()) => Ok(()),
+            Err(SendError::Full(msg)) => Err(SendError::Full(msg.into_inner())),
+            Err(SendError::Closed(msg)) => Err(SendError::Closed(msg.into_inner())),
+        }
     }
 
     pub fn send(&self, msg: M) -> RemoteRecipientRequest<Remote, M> {
-        RemoteRecipientRequest::new(self.tx.send(msg))
+        RemoteRecipientRequest::new(&self.tx, msg, self.timeout)
     }
 }
 
@@ -189,6 +790,99 @@ impl<M> Clone for RecipientProxySender<M>
     where M: RemoteMessage, M::Result: Send + Serialize + DeserializeOwned,
 {
     fn clone(&self) -> Self {
-        RecipientProxySender {m: PhantomData, tx: self.tx.clone()}
+        RecipientProxySender {m: PhantomData, tx: self.tx.clone(), timeout: self.timeout}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Handler {
+        id: u8,
+        closed: AtomicBool,
+    }
+
+    impl RemoteMessageHandler for Handler {
+        fn handle(&self, _: Format, _: Vec<u8>,
+                  _: Option<Instant>) -> Box<Future<Item=Vec<u8>, Error=u16>> {
+            Box::new(future::ok(vec![self.id]))
+        }
+
+        fn closed(&self) -> bool {
+            self.closed.load(Ordering::Relaxed)
+        }
+
+        fn as_any(&self) -> &Any {
+            self
+        }
+    }
+
+    fn pool(dispatch: Dispatch, count: u8) -> (ProviderPool, Vec<Arc<Handler>>) {
+        let handlers: Vec<_> = (0..count)
+            .map(|id| Arc::new(Handler{id: id, closed: AtomicBool::new(false)}))
+            .collect();
+        let pool = handlers.iter().fold(ProviderPool::new(dispatch), |pool, handler| {
+            let handler: Arc<RemoteMessageHandler> = handler.clone();
+            pool.with(handler)
+        });
+        (pool, handlers)
+    }
+
+    fn send(pool: &ProviderPool) -> Result<Vec<u8>, u16> {
+        pool.handle(Format::Json, Vec::new(), None).wait()
+    }
+
+    #[test]
+    fn test_round_robin() {
+        let (pool, handlers) = pool(Dispatch::RoundRobin, 3);
+        let ids: Vec<_> = (0..4).map(|_| send(&pool).unwrap()[0]).collect();
+        assert_eq!(ids, vec![0, 1, 2, 0]);
+
+        // stopped provider is skipped
+        handlers[1].closed.store(true, Ordering::Relaxed);
+        assert!(pool.has_closed());
+        let ids: Vec<_> = (0..4).map(|_| send(&pool).unwrap()[0]).collect();
+        assert_eq!(ids, vec![0, 2, 0, 2]);
+    }
+
+    #[test]
+    fn test_round_robin_shared_between_generations() {
+        let (pool, handlers) = pool(Dispatch::RoundRobin, 2);
+        assert_eq!(send(&pool), Ok(vec![0]));
+
+        handlers[0].closed.store(true, Ordering::Relaxed);
+        let pool = pool.without_closed();
+        assert_eq!(pool.len(), 1);
+        assert!(!pool.has_closed());
+        assert_eq!(send(&pool), Ok(vec![1]));
+    }
+
+    #[test]
+    fn test_least_busy() {
+        let (pool, handlers) = pool(Dispatch::LeastBusy, 3);
+
+        let first = pool.acquire().unwrap().1;
+        let second = pool.acquire().unwrap().1;
+        let third = pool.acquire().unwrap().1;
+        assert_eq!(send(&pool), Ok(vec![0]));
+
+        // provider with completed message is least busy
+        drop(second);
+        assert_eq!(send(&pool), Ok(vec![1]));
+        drop(first);
+        drop(third);
+
+        handlers[0].closed.store(true, Ordering::Relaxed);
+        assert_eq!(send(&pool), Ok(vec![1]));
+    }
+
+    #[test]
+    fn test_no_provider() {
+        let (pool, handlers) = pool(Dispatch::LeastBusy, 1);
+        handlers[0].closed.store(true, Ordering::Relaxed);
+        assert_eq!(send(&pool), Err(protocol::ERROR_NO_PROVIDER));
+        assert_eq!(send(&ProviderPool::new(Dispatch::RoundRobin)),
+                   Err(protocol::ERROR_NO_PROVIDER));
     }
 }
@@ -1,14 +1,18 @@
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::marker::PhantomData;
+use std::sync::{Arc, Mutex};
 
 use serde::Serialize;
 use serde::de::DeserializeOwned;
 use futures::{Async, Future, Poll};
+use futures::sync::oneshot;
 use tokio_core::reactor::Timeout;
 
 use actix::prelude::*;
-use actix::dev::{Message, MessageRecipient, SendError, MailboxError};
+use actix::dev::{Message, MessageRecipient, SendError};
 
+use error::RemoteError;
+use format::Format;
 use recipient::RecipientProxySender;
 
 
@@ -16,6 +20,46 @@ pub trait RemoteMessage: Message + Send + Serialize + DeserializeOwned
     where Self::Result: Send + Serialize + DeserializeOwned
 {
     fn type_id() -> &'static str;
+
+    /// Payload serialization format for this message type.
+    ///
+    /// By default format negotiated with remote node is used.
+    fn format() -> Option<Format> {
+        None
+    }
+
+    /// Default timeout of requests of this message type.
+    ///
+    /// By default world's request timeout is used.
+    fn default_timeout() -> DefaultTimeout {
+        DefaultTimeout::World
+    }
+
+    /// Routing key for sticky delivery.
+    ///
+    /// Messages with same key are delivered to same provider node
+    /// while set of providers does not change.
+    fn routing_key(&self) -> Option<String> {
+        None
+    }
+
+    /// Called on receiving node before message is passed to recipient.
+    ///
+    /// Deadline is set if sender uses `RemoteRecipientRequest::timeout()`,
+    /// result is discarded after deadline. Message could store deadline
+    /// in a `#[serde(skip)]` field.
+    fn set_deadline(&mut self, _deadline: Instant) {}
+}
+
+/// Default timeout of remote message type
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DefaultTimeout {
+    /// World's request timeout is used
+    World,
+    /// Requests never time out, even if world's request timeout is set
+    Never,
+    /// Requests time out after specified duration
+    After(Duration),
 }
 
 pub struct Remote;
@@ -27,7 +71,7 @@ impl<M> MessageRecipient<M> for Remote
     type Transport = RecipientProxySender<M>;
 
     type SendError = SendError<M>;
-    type MailboxError = MailboxError;
+    type MailboxError = RemoteError;
     type Request = RemoteRecipientRequest<Self, M>;
 
     fn do_send(tx: &Self::Transport, msg: M) -> Result<(), SendError<M>> {
@@ -47,61 +91,136 @@ impl<M> MessageRecipient<M> for Remote
     }
 }
 
+/// Deadline shared between request and envelope
+///
+/// Default deadline is set before message is sent, request could override
+/// it later, recipient proxy reads it when message is dispatched.
+#[derive(Clone, Default)]
+pub(crate) struct Deadline(Arc<Mutex<Option<Instant>>>);
+
+impl Deadline {
+    fn new(deadline: Option<Instant>) -> Deadline {
+        Deadline(Arc::new(Mutex::new(deadline)))
+    }
+
+    fn set(&self, deadline: Instant) {
+        *self.0.lock().unwrap() = Some(deadline);
+    }
+
+    fn get(&self) -> Option<Instant> {
+        *self.0.lock().unwrap()
+    }
+}
+
 pub struct RemoteMessageEnvelope<M: RemoteMessage>
     where M::Result: Send + Serialize + DeserializeOwned
 {
     msg: M,
+    cancel: Option<oneshot::Sender<()>>,
+    deadline: Deadline,
 }
 
 impl<M: RemoteMessage> RemoteMessageEnvelope<M>
     where M::Result: Send + Serialize + DeserializeOwned
 {
+    pub(crate) fn new(msg: M, cancel: Option<oneshot::Sender<()>>, deadline: Deadline) -> Self {
+        RemoteMessageEnvelope{msg: msg, cancel: cancel, deadline: deadline}
+    }
+
     pub fn into_inner(self) -> M {
         self.msg
     }
+
+    /// Message, cancellation channel and deadline. Request is cancelled
+    /// when receiving side of the channel is dropped
+    pub(crate) fn into_parts(self) -> (M, Option<oneshot::Sender<()>>, Option<Instant>) {
+        (self.msg, self.cancel, self.deadline.get())
+    }
+}
+
+/// Envelope is sent to recipient proxy, result contains delivery errors
+impl<M: RemoteMessage + 'static> Message for RemoteMessageEnvelope<M>
+    where M::Result: Send + Serialize + DeserializeOwned
+{
+    type Result = Result<M::Result, RemoteError>;
 }
 
 impl<M: RemoteMessage> From<M> for RemoteMessageEnvelope<M>
     where M::Result: Send + Serialize + DeserializeOwned
 {
     fn from(msg: M) -> RemoteMessageEnvelope<M> {
-        RemoteMessageEnvelope{msg: msg}
+        RemoteMessageEnvelope{msg: msg, cancel: None, deadline: Deadline::default()}
     }
 }
 
 use recipient::RecipientProxy;
 
 /// `RecipientRequest` is a `Future` which represents asynchronous message sending process.
+///
+/// Dropping request before completion or request timeout cancels
+/// message processing on remote node.
 #[must_use = "future do nothing unless polled"]
 pub struct RemoteRecipientRequest<T, M>
     where T: MessageRecipient<M>,
           M: RemoteMessage + 'static, M::Result: Send + Serialize + DeserializeOwned
 {
-    rx: actix::dev::Request<Syn, RecipientProxy<M>, M>,
-    timeout: Option<Timeout>,
+    rx: actix::dev::Request<Syn, RecipientProxy<M>, RemoteMessageEnvelope<M>>,
+    timer: Option<Timeout>,
+    expires: Option<Instant>,
+    deadline: Deadline,
+    cancel: Option<oneshot::Receiver<()>>,
     _t: PhantomData<T>,
 }
 
 impl<T, M> RemoteRecipientRequest<T, M>
-    where T: MessageRecipient<M, MailboxError=MailboxError>,
+    where T: MessageRecipient<M, MailboxError=RemoteError>,
           M: RemoteMessage + 'static, M::Result: Send + Serialize + DeserializeOwned
 {
-    pub(crate) fn new(rx: actix::dev::Request<Syn, RecipientProxy<M>, M>)
+    /// Message type's default timeout is used if set, otherwise world's one
+    pub(crate) fn new(addr: &Addr<Syn, RecipientProxy<M>>, msg: M, timeout: Option<Duration>)
                       -> RemoteRecipientRequest<T, M>
     {
-        RemoteRecipientRequest{rx: rx, timeout: None, _t: PhantomData}
+        let timeout = match M::default_timeout() {
+            DefaultTimeout::World => timeout,
+            DefaultTimeout::Never => None,
+            DefaultTimeout::After(dur) => Some(dur),
+        };
+        // proxy could dispatch message right away, deadline has to be set before send
+        let expires = timeout.map(|dur| Instant::now() + dur);
+        let deadline = Deadline::new(expires);
+
+        let (tx, rx) = oneshot::channel();
+        RemoteRecipientRequest{
+            rx: addr.send(RemoteMessageEnvelope::new(msg, Some(tx), deadline.clone())),
+            timer: None, expires: expires, deadline: deadline, cancel: Some(rx), _t: PhantomData}
     }
 
-    /// Set message delivery timeout
+    /// Set message delivery timeout, replaces default timeout
+    ///
+    /// Timeout is passed to remote node as message deadline,
+    /// if it is set before recipient proxy dispatches the message.
     pub fn timeout(mut self, dur: Duration) -> Self {
-        self.timeout = Some(Timeout::new(dur, Arbiter::handle()).unwrap());
+        let expires = Instant::now() + dur;
+        self.deadline.set(expires);
+        self.expires = Some(expires);
+        self.timer = None;
         self
     }
 
-    fn poll_timeout(&mut self) -> Poll<M::Result, MailboxError> {
-        if let Some(ref mut timeout) = self.timeout {
-            match timeout.poll() {
-                Ok(Async::Ready(())) => Err(MailboxError::Timeout),
+    fn poll_timeout(&mut self) -> Poll<M::Result, RemoteError> {
+        // timer is created on first poll, request could be built outside of arbiter
+        if self.timer.is_none() {
+            if let Some(expires) = self.expires {
+                self.timer = Some(Timeout::new_at(expires, Arbiter::handle()).unwrap());
+            }
+        }
+        if let Some(ref mut timer) = self.timer {
+            match timer.poll() {
+                Ok(Async::Ready(())) => {
+                    // cancel remote processing
+                    self.cancel.take();
+                    Err(RemoteError::Timeout)
+                },
                 Ok(Async::NotReady) => Ok(Async::NotReady),
                 Err(_) => unreachable!()
             }
@@ -112,7 +231,7 @@ impl<T, M> RemoteRecipientRequest<T, M>
 }
 
 impl<T, M> Future for RemoteRecipientRequest<T, M>
-    where T: MessageRecipient<M, SendError=SendError<M>, MailboxError=MailboxError>,
+    where T: MessageRecipient<M, SendError=SendError<M>, MailboxError=RemoteError>,
           M: RemoteMessage + 'static, M::Result: Send + Serialize + DeserializeOwned
 {
     type Item = M::Result;
@@ -120,11 +239,12 @@ impl<T, M> Future for RemoteRecipientRequest<T, M>
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
         match self.rx.poll() {
-            Ok(Async::Ready(item)) => Ok(Async::Ready(item)),
+            Ok(Async::Ready(Ok(item))) => Ok(Async::Ready(item)),
+            Ok(Async::Ready(Err(err))) => Err(err),
             Ok(Async::NotReady) => {
                 self.poll_timeout()
             }
-            Err(_) => Err(MailboxError::Closed),
+            Err(err) => Err(err.into()),
         }
     }
 }
@@ -0,0 +1,255 @@
+use std::collections::BTreeMap;
+use rand::{self, Rng};
+
+/// Strategy for selecting remote node for message delivery
+///
+/// Routing strategy is selected per message type with
+/// `World::get_recipient_with()`. Messages with routing key
+/// (`RemoteMessage::routing_key()`) are always routed with consistent
+/// hashing, so same key is delivered to same provider node.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RoutingStrategy {
+    /// Send messages to providers in turn
+    RoundRobin,
+    /// Send message to random provider
+    Random,
+    /// Send message to provider with least number of outstanding requests
+    LeastPending,
+    /// Send message to all providers, first successful result is returned
+    Broadcast,
+}
+
+impl Default for RoutingStrategy {
+    fn default() -> RoutingStrategy {
+        RoutingStrategy::RoundRobin
+    }
+}
+
+impl RoutingStrategy {
+    /// Select nodes for message without routing key
+    ///
+    /// `nodes` contains connection status and number of pending requests
+    /// of every provider node, `next` is round-robin position.
+    /// Nodes with established connection are preferred.
+    pub(crate) fn select(&self, nodes: &[(bool, usize)], next: &mut usize) -> Vec<usize> {
+        let mut candidates: Vec<usize> = (0..nodes.len())
+            .filter(|idx| nodes[*idx].0)
+            .collect();
+        if candidates.is_empty() {
+            candidates = (0..nodes.len()).collect();
+        }
+        if candidates.is_empty() {
+            return candidates
+        }
+
+        match *self {
+            RoutingStrategy::RoundRobin => {
+                *next = next.wrapping_add(1);
+                vec![candidates[*next % candidates.len()]]
+            },
+            RoutingStrategy::Random => {
+                vec![candidates[rand::thread_rng().gen_range(0, candidates.len())]]
+            },
+            RoutingStrategy::LeastPending => candidates.into_iter()
+                .min_by_key(|idx| nodes[*idx].1)
+                .into_iter().collect(),
+            RoutingStrategy::Broadcast => candidates,
+        }
+    }
+}
+
+/// Delivery policy for message types with provider registered in same process
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LocalPolicy {
+    /// Deliver messages to local provider only
+    LocalOnly,
+    /// Deliver messages to local provider if it is registered,
+    /// otherwise to remote nodes
+    LocalPreferred,
+    /// Deliver messages to remote nodes only
+    RemoteOnly,
+}
+
+impl Default for LocalPolicy {
+    fn default() -> LocalPolicy {
+        LocalPolicy::LocalPreferred
+    }
+}
+
+/// Dispatch strategy for pool of local providers of same message type
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Dispatch {
+    /// Pass messages to providers in turn
+    RoundRobin,
+    /// Pass message to provider with least number of messages in progress
+    LeastBusy,
+}
+
+impl Default for Dispatch {
+    fn default() -> Dispatch {
+        Dispatch::RoundRobin
+    }
+}
+
+
+/// Number of virtual nodes per provider on hash ring
+const VIRTUAL_NODES: usize = 160;
+
+/// Consistent hash ring
+///
+/// Every provider node is placed on the ring multiple times,
+/// routing key is served by first node clockwise from the key hash.
+/// Only keys of joining or leaving node move to other nodes.
+#[derive(Default)]
+pub(crate) struct HashRing {
+    ring: BTreeMap<u64, String>,
+}
+
+impl HashRing {
+    pub fn add(&mut self, node: &str) {
+        for idx in 0..VIRTUAL_NODES {
+            self.ring.insert(hash(format!("{}#{}", node, idx).as_bytes()), node.to_owned());
+        }
+    }
+
+    pub fn remove(&mut self, node: &str) {
+        for idx in 0..VIRTUAL_NODES {
+            let h = hash(format!("{}#{}", node, idx).as_bytes());
+            if self.ring.get(&h).map(|n| n == node).unwrap_or(false) {
+                self.ring.remove(&h);
+            }
+        }
+    }
+
+    /// Node responsible for routing key
+    pub fn get(&self, key: &str) -> Option<&str> {
+        let h = hash(key.as_bytes());
+        self.ring.range(h..).next()
+            .or_else(|| self.ring.iter().next())
+            .map(|(_, node)| node.as_str())
+    }
+}
+
+/// Stable 64-bit hash, fnv-1a with final avalanche step.
+///
+/// Hash has to be the same on every node and every build,
+/// so std hasher can not be used.
+fn hash(data: &[u8]) -> u64 {
+    let mut h: u64 = 0xcbf2_9ce4_8422_2325;
+    for b in data {
+        h ^= u64::from(*b);
+        h = h.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    h ^= h >> 33;
+    h
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_select_round_robin() {
+        let nodes = [(true, 0), (false, 0), (true, 0)];
+        let mut next = 0;
+        let selected: Vec<_> = (0..4)
+            .map(|_| RoutingStrategy::RoundRobin.select(&nodes, &mut next))
+            .collect();
+        assert_eq!(selected, vec![vec![2], vec![0], vec![2], vec![0]]);
+    }
+
+    #[test]
+    fn test_select_random() {
+        let nodes = [(false, 0), (true, 0), (false, 0)];
+        let mut next = 0;
+        for _ in 0..10 {
+            assert_eq!(RoutingStrategy::Random.select(&nodes, &mut next), vec![1]);
+        }
+    }
+
+    #[test]
+    fn test_select_least_pending() {
+        let nodes = [(true, 3), (false, 0), (true, 1), (true, 2)];
+        let mut next = 0;
+        assert_eq!(RoutingStrategy::LeastPending.select(&nodes, &mut next), vec![2]);
+    }
+
+    #[test]
+    fn test_select_broadcast() {
+        let nodes = [(true, 0), (false, 0), (true, 0)];
+        let mut next = 0;
+        assert_eq!(RoutingStrategy::Broadcast.select(&nodes, &mut next), vec![0, 2]);
+    }
+
+    #[test]
+    fn test_select_not_connected() {
+        // nodes that are still connecting are used if none is connected
+        let nodes = [(false, 2), (false, 1)];
+        let mut next = 0;
+        assert_eq!(RoutingStrategy::LeastPending.select(&nodes, &mut next), vec![1]);
+        assert_eq!(RoutingStrategy::Broadcast.select(&nodes, &mut next), vec![0, 1]);
+        assert!(RoutingStrategy::RoundRobin.select(&[], &mut next).is_empty());
+    }
+
+    fn owners(ring: &HashRing, keys: &[String]) -> Vec<String> {
+        keys.iter().map(|key| ring.get(key).unwrap().to_owned()).collect()
+    }
+
+    #[test]
+    fn test_hash_is_stable() {
+        assert_eq!(hash(b""), 0xecba_3df2_c338_3c52);
+        assert_eq!(hash(b"a"), 0xed81_70de_1919_a24d);
+        assert_eq!(hash(b"127.0.0.1:8080#0"), 0x766b_339c_1313_f4c9);
+    }
+
+    #[test]
+    fn test_empty_ring() {
+        let ring = HashRing::default();
+        assert_eq!(ring.get("key"), None);
+    }
+
+    #[test]
+    fn test_add_node_moves_only_its_keys() {
+        let keys: Vec<_> = (0..1000).map(|i| format!("key-{}", i)).collect();
+        let mut ring = HashRing::default();
+        ring.add("127.0.0.1:8080");
+        ring.add("127.0.0.1:8081");
+        let before = owners(&ring, &keys);
+
+        ring.add("127.0.0.1:8082");
+        let after = owners(&ring, &keys);
+
+        let mut moved = 0;
+        for (old, new) in before.iter().zip(after.iter()) {
+            if old != new {
+                assert_eq!(new, "127.0.0.1:8082");
+                moved += 1;
+            }
+        }
+        assert!(moved > 0);
+    }
+
+    #[test]
+    fn test_remove_node_moves_only_its_keys() {
+        let keys: Vec<_> = (0..1000).map(|i| format!("key-{}", i)).collect();
+        let mut ring = HashRing::default();
+        ring.add("127.0.0.1:8080");
+        ring.add("127.0.0.1:8081");
+        ring.add("127.0.0.1:8082");
+        let before = owners(&ring, &keys);
+
+        ring.remove("127.0.0.1:8081");
+        let after = owners(&ring, &keys);
+
+        for (old, new) in before.iter().zip(after.iter()) {
+            if old == "127.0.0.1:8081" {
+                assert_ne!(new, "127.0.0.1:8081");
+            } else {
+                assert_eq!(old, new);
+            }
+        }
+        assert!(before.iter().any(|node| node == "127.0.0.1:8081"));
+    }
+}
@@ -1,8 +1,9 @@
-use std::io;
+use std::{cmp, io};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use std::collections::HashMap;
 
-use futures::unsync::oneshot::channel;
+use futures::Future;
 use tokio_io::{AsyncRead, AsyncWrite};
 use tokio_io::io::WriteHalf;
 use tokio_io::codec::FramedRead;
@@ -11,8 +12,10 @@ use actix::prelude::*;
 use msgs;
 use msgs::NodeConnected;
 use world::World;
+use config::Config;
 use recipient::RemoteMessageHandler;
-use protocol::{Request, Response, NetworkServerCodec};
+use protocol::{self, Request, Response,
+               ChunkBuffer, CodecState, Framing, NetworkServerCodec};
 
 /// Worker accepts messages from other network hosts and
 /// pass them to local recipients
@@ -21,6 +24,15 @@ pub struct NetworkWorker<T> where T: AsyncRead + AsyncWrite {
     net: Addr<Unsync, World>,
     handlers: HashMap<&'static str, Arc<RemoteMessageHandler>>,
     framed: actix::io::FramedWrite<WriteHalf<T>, NetworkServerCodec>,
+    config: Config,
+    chunk_size: usize,
+    max_payload: usize,
+    chunks: ChunkBuffer,
+    missed: usize,
+    handshaked: bool,
+    features: Vec<String>,
+    /// Handlers in progress and their expiration timers
+    tasks: HashMap<u64, (SpawnHandle, Option<SpawnHandle>)>,
 }
 
 impl<T> NetworkWorker<T>
@@ -28,29 +40,70 @@ impl<T> NetworkWorker<T>
 {
     pub fn start(id: usize, io: T,
                  handlers: HashMap<&'static str, Arc<RemoteMessageHandler>>,
-                 net: Addr<Unsync, World>) -> Addr<Unsync, Self>
+                 net: Addr<Unsync, World>, config: Config) -> Addr<Unsync, Self>
     {
         Actor::create(move |ctx| {
             let (r, w) = io.split();
+            let state = CodecState::new(config.max_frame_size);
 
             // read side of the connection
-            ctx.add_stream(FramedRead::new(r, NetworkServerCodec::default()));
+            ctx.add_stream(FramedRead::new(r, NetworkServerCodec::new(state.clone())));
 
             // write side of the connection
-            let mut framed = actix::io::FramedWrite::new(
-                w, NetworkServerCodec::default(), ctx);
-            framed.write(Response::Handshake);
-
-            // send list of supported messages
-            framed.write(Response::Supported(
-                handlers.keys().map(|s| s.to_string()).collect()));
-            NetworkWorker{id: id, net: net, handlers: handlers, framed: framed}
+            let framed = actix::io::FramedWrite::new(
+                w, NetworkServerCodec::new(state), ctx);
+            NetworkWorker{id: id, net: net, handlers: handlers, framed: framed,
+                          chunk_size: Framing::Json.chunk_size(config.max_frame_size),
+                          max_payload: usize::max_value(),
+                          chunks: ChunkBuffer::new(config.max_message_size),
+                          missed: 0,
+                          handshaked: false,
+                          features: Vec::new(),
+                          tasks: HashMap::new(),
+                          config: config}
         })
     }
+
+    /// Send ping to remote node, drop connection if remote node
+    /// did not respond to previous pings
+    fn heartbeat(&mut self, ctx: &mut Context<Self>) {
+        if self.missed >= self.config.heartbeat_misses {
+            error!("Network worker {} peer did not respond to {} pings",
+                   self.id, self.missed);
+            return ctx.stop()
+        }
+        self.missed += 1;
+        self.framed.write(Response::Ping);
+        ctx.run_later(self.config.heartbeat_interval, |act, ctx| act.heartbeat(ctx));
+    }
+
+    fn has_feature(&self, feature: &str) -> bool {
+        self.features.iter().any(|f| f == feature)
+    }
+
+    /// Send message result, large payloads are sent as sequence of chunks
+    fn write_result(&mut self, msg_id: u64, payload: Vec<u8>) {
+        // remote node would drop connection on oversized result
+        if payload.len() > self.max_payload {
+            warn!("Result of message {} size {} exceeds limit {} of network worker {}",
+                  msg_id, payload.len(), self.max_payload, self.id);
+            self.framed.write(Response::Error(msg_id, protocol::ERROR_TOO_LARGE));
+            return
+        }
+        let (chunks, payload) = protocol::split_payload(payload, self.chunk_size);
+        for chunk in chunks {
+            self.framed.write(Response::Chunk(chunk));
+        }
+        self.framed.write(Response::Result(msg_id, payload));
+    }
 }
 
 impl<T> Actor for NetworkWorker<T> where T: AsyncRead + AsyncWrite + 'static {
     type Context = Context<Self>;
+
+    fn stopped(&mut self, _: &mut Self::Context) {
+        self.net.do_send(msgs::WorkerDisconnected(self.id));
+    }
 }
 
 impl<T> actix::io::WriteHandler<io::Error> for NetworkWorker<T>
@@ -61,34 +114,123 @@ impl<T> StreamHandler<Request, io::Error> for NetworkWorker<T>
     where T: AsyncRead + AsyncWrite + 'static
 {
     fn finished(&mut self, ctx: &mut Self::Context) {
-        self.net.do_send(msgs::WorkerDisconnected(self.id));
         ctx.stop();
     }
 
     /// This is main event loop for client connection
     fn handle(&mut self, msg: Request, ctx: &mut Self::Context) {
+        // any frame from remote node proves connection is alive
+        self.missed = 0;
+
         match msg {
-            Request::Handshake(addr) => self.net.do_send(NodeConnected(addr)),
-            Request::Message(msg_id, type_id, _, body) => {
-                debug!("RECEIVED MESSAGE: {:?} {:?} {:?}", msg_id, type_id, body);
-                if let Some(ref handler) = self.handlers.get(type_id.as_str()) {
-                    let (tx, rx) = channel();
-                    handler.handle(body, tx);
-
-                    rx.into_actor(self)
-                        .then(move |res, act, _| {
-                            match res {
-                                Ok(res) => act.framed.write(Response::Result(msg_id, res)),
-                                Err(_) => (),
+            Request::Handshake(hs) => {
+                let handshake = match hs.negotiate(&self.config) {
+                    Ok(handshake) => handshake,
+                    Err(err) => {
+                        error!("Handshake with network node {} failed: {}", hs.addr, err);
+                        self.framed.write(Response::HandshakeError(err));
+                        self.framed.close();
+                        return
+                    }
+                };
+                let frame_size = cmp::min(self.config.max_frame_size, hs.max_frame_size);
+                self.chunk_size = if handshake.has_feature("chunking") {
+                    handshake.framing.chunk_size(frame_size)
+                } else {
+                    usize::max_value()
+                };
+                self.max_payload = protocol::max_payload(
+                    handshake.framing, handshake.has_feature("chunking"),
+                    frame_size, hs.max_message_size);
+                if handshake.has_feature("heartbeat") {
+                    ctx.run_later(
+                        self.config.heartbeat_interval, |act, ctx| act.heartbeat(ctx));
+                }
+                self.features = handshake.features.clone();
+                self.handshaked = true;
+                self.framed.write(Response::Handshake(handshake));
+
+                // send list of supported messages
+                self.framed.write(Response::Supported(
+                    self.handlers.keys().map(|s| s.to_string()).collect()));
+
+                self.net.do_send(NodeConnected{node: hs.addr, worker: self.id})
+            },
+            Request::Chunk(chunk) => {
+                if let Err(err) = self.chunks.push(chunk) {
+                    error!("Network worker {} error: {}", self.id, err);
+                    ctx.stop();
+                }
+            },
+            Request::Message(msg_id, type_id, _, format, body, deadline) => {
+                let body = self.chunks.complete(body);
+                debug!("RECEIVED MESSAGE: {:?} {:?} {:?}", msg_id, type_id, format);
+                let now = Instant::now();
+                let deadline = deadline.map(|ms| now + Duration::from_millis(ms));
+                if deadline.map(|d| d <= now).unwrap_or(false) {
+                    debug!("Network worker {} got expired message {}", self.id, msg_id);
+                    return self.framed.write(Response::Error(msg_id, protocol::ERROR_EXPIRED))
+                }
+
+                let handler = self.handlers.get(type_id.as_str()).cloned();
+                if let Some(handler) = handler {
+                    let task = ctx.spawn(
+                        handler.handle(format, body, deadline)
+                            .into_actor(self)
+                            .then(move |res, act, ctx| {
+                                if let Some((_, Some(timer))) = act.tasks.remove(&msg_id) {
+                                    ctx.cancel_future(timer);
+                                }
+                                match res {
+                                    Ok(res) => act.write_result(msg_id, res),
+                                    Err(code) => act.framed.write(Response::Error(msg_id, code)),
+                                }
+                                actix::fut::ok(())
+                            }));
+
+                    // abandon handler, sender does not wait for result after deadline
+                    let timer = deadline.map(|deadline| {
+                        ctx.run_later(deadline - now, move |act, ctx| {
+                            if let Some((task, _)) = act.tasks.remove(&msg_id) {
+                                debug!("Network worker {} message {} expired", act.id, msg_id);
+                                ctx.cancel_future(task);
+                                act.framed.write(
+                                    Response::Error(msg_id, protocol::ERROR_EXPIRED));
                             }
-                            actix::fut::ok(())
                         })
-                        .spawn(ctx)
+                    });
+                    self.tasks.insert(msg_id, (task, timer));
+                } else {
+                    warn!("Network worker {} got message for unknown type: {}",
+                          self.id, type_id);
+                    self.framed.write(Response::Error(msg_id, protocol::ERROR_NO_PROVIDER));
+                }
+            },
+            Request::Notify(type_id, _, format, body, deadline) => {
+                let body = self.chunks.complete(body);
+                let deadline = deadline.map(|ms| Instant::now() + Duration::from_millis(ms));
+                let handler = self.handlers.get(type_id.as_str()).cloned();
+                if let Some(handler) = handler {
+                    // result is not sent back
+                    Arbiter::handle().spawn(
+                        handler.handle(format, body, deadline).then(|_| Ok(())));
+                } else {
+                    warn!("Network worker {} got notification for unknown type: {}",
+                          self.id, type_id);
+                }
+            },
+            Request::Cancel(msg_id) => {
+                // abort handler, result is not sent
+                if let Some((task, timer)) = self.tasks.remove(&msg_id) {
+                    debug!("Network worker {} cancels message {}", self.id, msg_id);
+                    ctx.cancel_future(task);
+                    if let Some(timer) = timer {
+                        ctx.cancel_future(timer);
+                    }
                 }
             },
-            _ => {
-                println!("CLIENT REQ: {:?}", msg);
-            }
+            Request::Ping => self.framed.write(Response::Pong),
+            Request::Pong => (),
         }
     }
 }
@@ -111,7 +253,24 @@ impl<T> Handler<msgs::ProvideRecipient> for NetworkWorker<T>
     type Result = ();
 
     fn handle(&mut self, msg: msgs::ProvideRecipient, _: &mut Self::Context) {
-        self.framed.write(Response::Supported(vec![msg.type_id.to_owned()]));
-        self.handlers.insert(msg.type_id, msg.handler);
+        // pool of already announced type could be updated,
+        // handshake announces all registered types
+        if self.handlers.insert(msg.type_id, msg.handler).is_none() && self.handshaked {
+            self.framed.write(Response::Supported(vec![msg.type_id.to_owned()]));
+        }
+    }
+}
+
+/// Recipient is unregistered
+impl<T> Handler<msgs::WithdrawRecipient> for NetworkWorker<T>
+    where T: AsyncRead + AsyncWrite + 'static
+{
+    type Result = ();
+
+    fn handle(&mut self, msg: msgs::WithdrawRecipient, _: &mut Self::Context) {
+        self.handlers.remove(msg.type_id);
+        if self.has_feature("unsupported") {
+            self.framed.write(Response::Unsupported(vec![msg.type_id.to_owned()]));
+        }
     }
 }
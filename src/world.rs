@@ -1,12 +1,15 @@
-use std::{io, net};
+use std::{cmp, io, net};
 use std::any::Any;
 use std::sync::Arc;
 use std::time::Duration;
 use std::collections::{HashMap, HashSet};
 
 use actix::prelude::*;
+use actix::prelude::{Response as ActixResponse};
 use actix::actors::signal;
+use actix::dev::ToEnvelope;
 use futures::Future;
+use futures::unsync::oneshot;
 use serde::Serialize;
 use serde::de::DeserializeOwned;
 use tokio_core::net::{TcpStream, TcpListener};
@@ -14,33 +17,60 @@ use tokio_core::reactor::Timeout;
 
 use msgs;
 use utils;
+use error::RemoteError;
+use format::Format;
+use compression::Compression;
+use config::{self, Config, Overflow};
+use protocol::Framing;
 use worker::NetworkWorker;
-use node::{NetworkNode, NodeInformation};
+use node::{NetworkNode, NodeInformation, NodeStatus};
 use remote::{Remote, RemoteMessage};
-use recipient::{Provider, RecipientProxy,
+use routing::{Dispatch, LocalPolicy, RoutingStrategy};
+use recipient::{Provider, ProviderPool, RecipientProxy,
                 RecipientProxySender, RemoteMessageHandler};
 
 
 struct Proxy {
     addr: Box<Any>,
     service: Recipient<Unsync, msgs::TypeSupported>,
+    gone: Recipient<Unsync, msgs::NodeGone>,
+    local: Recipient<Unsync, msgs::LocalPool>,
+    local_gone: Recipient<Unsync, msgs::LocalRecipientGone>,
+}
+
+/// Pending `ClusterReady` request
+struct ReadyWaiter {
+    type_id: String,
+    nodes: usize,
+    tx: oneshot::Sender<Result<(), RemoteError>>,
 }
 
 pub struct World {
     addr: String,
     addrs: HashMap<String, NodeInformation>,
     nodes: HashMap<String, Addr<Unsync, NetworkNode>>,
+    removed: HashSet<String>,
+    up: HashSet<String>,
+    peers: HashMap<usize, String>,
+    subscribers: Vec<Recipient<Syn, msgs::ClusterEvent>>,
     types: HashMap<String, HashSet<String>>,
     sockets: HashMap<net::SocketAddr, net::TcpListener>,
     wid: usize,
     workers: HashMap<usize, Addr<Unsync, NetworkWorker<TcpStream>>>,
-    handlers: HashMap<&'static str, Arc<RemoteMessageHandler>>,
+    handlers: HashMap<&'static str, Arc<ProviderPool>>,
     recipients: HashMap<&'static str, Proxy>,
+    waiters: HashMap<usize, ReadyWaiter>,
+    wait_id: usize,
+    config: Config,
     exit: bool,
 }
 
 impl Actor for World {
     type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.check_stopped(ctx);
+    }
 }
 
 impl World {
@@ -48,12 +78,19 @@ impl World {
         let net = World{addr: addr.clone(),
                         addrs: HashMap::new(),
                         nodes: HashMap::new(),
+                        removed: HashSet::new(),
+                        up: HashSet::new(),
+                        peers: HashMap::new(),
+                        subscribers: Vec::new(),
                         types: HashMap::new(),
                         sockets: HashMap::new(),
                         wid: 0,
                         workers: HashMap::new(),
                         handlers: HashMap::new(),
                         recipients: HashMap::new(),
+                        waiters: HashMap::new(),
+                        wait_id: 0,
+                        config: Config::default(),
                         exit: false};
         Ok(net.bind(addr)?)
     }
@@ -94,39 +131,399 @@ impl World {
         self
     }
 
+    /// Preferred payload serialization format, default is `Format::Json`
+    ///
+    /// Actual format is negotiated with every node during handshake,
+    /// `Format::Json` is used if remote node does not support preferred format.
+    pub fn format(mut self, format: Format) -> Self {
+        if format.is_available() {
+            self.config.format = format;
+        } else {
+            warn!("Format is not available: {:?}", format);
+        }
+        self
+    }
+
+    /// Preferred transport framing, default is `Framing::Binary`
+    ///
+    /// Framing is selected during handshake, `Framing::Json`
+    /// is used with nodes that do not support binary framing.
+    pub fn framing(mut self, framing: Framing) -> Self {
+        self.config.framing = framing;
+        self
+    }
+
+    /// Preferred payload compression, default is `Compression::None`
+    ///
+    /// Compression is negotiated with every node during handshake.
+    pub fn compression(mut self, compression: Compression) -> Self {
+        if compression.is_available() {
+            self.config.compression = compression;
+        } else {
+            warn!("Compression is not available: {:?}", compression);
+        }
+        self
+    }
+
+    /// Maximum size of single frame, default is 1Mb
+    ///
+    /// Larger messages are split into chunks. Smaller of local and remote
+    /// limits is used for each connection.
+    pub fn max_frame_size(mut self, size: usize) -> Self {
+        self.config.max_frame_size = cmp::max(size, config::MIN_FRAME_SIZE);
+        self
+    }
+
+    /// Maximum size of message reassembled from chunks, default is 64Mb
+    pub fn max_message_size(mut self, size: usize) -> Self {
+        self.config.max_message_size = size;
+        self
+    }
+
+    /// Heartbeat settings, default is ping every 5 seconds
+    /// and drop connection after 3 unanswered pings
+    pub fn heartbeat(mut self, interval: Duration, max_missed: usize) -> Self {
+        self.config.heartbeat_interval = interval;
+        self.config.heartbeat_misses = cmp::max(max_missed, 1);
+        self
+    }
+
+    /// Outbound queue settings, default is 1024 messages, new messages
+    /// are rejected when queue is full and queued messages expire after 30 seconds
+    ///
+    /// Messages are queued while connection to remote node is being established
+    /// and get sent after handshake completes.
+    pub fn queue(mut self, capacity: usize, overflow: Overflow, timeout: Duration) -> Self {
+        self.config.queue_capacity = capacity;
+        self.config.queue_overflow = overflow;
+        self.config.queue_timeout = timeout;
+        self
+    }
+
+    /// Delivery policy for message types with provider registered
+    /// in same process, default is `LocalPolicy::LocalPreferred`
+    pub fn local_policy(mut self, policy: LocalPolicy) -> Self {
+        self.config.local_policy = policy;
+        self
+    }
+
+    /// Dispatch strategy for multiple local recipients of same
+    /// message type, default is `Dispatch::RoundRobin`
+    pub fn dispatch(mut self, dispatch: Dispatch) -> Self {
+        self.config.dispatch = dispatch;
+        self
+    }
+
+    /// Default timeout of remote requests, default is 60 seconds.
+    /// `None` disables default timeout.
+    ///
+    /// Message type could override it with `RemoteMessage::default_timeout()`,
+    /// single request with `RemoteRecipientRequest::timeout()`.
+    pub fn request_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.config.request_timeout = timeout;
+        self
+    }
+
+    /// Hold messages in recipient proxy until provider for message type
+    /// becomes available, by default messages fail with `RemoteError::NoProvider`
+    ///
+    /// Up to `capacity` messages are kept for each type, new messages are
+    /// rejected with `RemoteError::QueueFull`. Messages expire after `timeout`.
+    pub fn await_provider(mut self, capacity: usize, timeout: Duration) -> Self {
+        self.config.await_provider = Some((capacity, timeout));
+        self
+    }
+
     /// Create remote recipient for specific message type
+    ///
+    /// Messages are distributed across providers with `RoutingStrategy::RoundRobin`
+    /// unless different strategy is selected with `get_recipient_with()`.
     pub fn get_recipient<M>(&mut self) -> Recipient<Remote, M>
         where M: RemoteMessage + 'static,
               M::Result: Send + Serialize + DeserializeOwned
+    {
+        let (_, saddr) = self.get_proxy::<M>(RoutingStrategy::default());
+        Recipient::new(RecipientProxySender::new(saddr, self.config.request_timeout))
+    }
+
+    /// Create remote recipient for specific message type with
+    /// specific routing strategy.
+    ///
+    /// Routing strategy is shared by all recipients of this message type.
+    pub fn get_recipient_with<M>(&mut self, routing: RoutingStrategy) -> Recipient<Remote, M>
+        where M: RemoteMessage + 'static,
+              M::Result: Send + Serialize + DeserializeOwned
+    {
+        let (addr, saddr) = self.get_proxy::<M>(routing);
+        addr.do_send(msgs::SetRouting(routing));
+        Recipient::new(RecipientProxySender::new(saddr, self.config.request_timeout))
+    }
+
+    fn get_proxy<M>(&mut self, routing: RoutingStrategy)
+                    -> (Addr<Unsync, RecipientProxy<M>>, Addr<Syn, RecipientProxy<M>>)
+        where M: RemoteMessage + 'static,
+              M::Result: Send + Serialize + DeserializeOwned
     {
         if let Some(info) = self.recipients.get(M::type_id()) {
-            if let Some(&(_, ref saddr)) = info.addr.downcast_ref
+            if let Some(&(ref addr, ref saddr)) = info.addr.downcast_ref
                 ::<(Addr<Unsync, RecipientProxy<M>>, Addr<Syn, RecipientProxy<M>>)>()
             {
-                return Recipient::new(RecipientProxySender::new(saddr.clone()))
+                return (addr.clone(), saddr.clone())
             }
         }
 
         let (addr, saddr): (Addr<Unsync, RecipientProxy<M>>,
-                            Addr<Syn, RecipientProxy<M>>) = RecipientProxy::new().start();
-        self.recipients.insert(
-            M::type_id(), Proxy{addr: Box::new(addr.clone()),
-                                service: addr.clone().recipient()});
+                            Addr<Syn, RecipientProxy<M>>) =
+            RecipientProxy::new(
+                routing, self.config.local_policy, self.config.await_provider).start();
+        let proxy = Proxy{addr: Box::new((addr.clone(), saddr.clone())),
+                          service: addr.clone().recipient(),
+                          gone: addr.clone().recipient(),
+                          local: addr.clone().recipient(),
+                          local_gone: addr.clone().recipient()};
+
+        // recipient is provided in same process
+        if let Some(pool) = self.handlers.get(M::type_id()) {
+            let _ = proxy.local.do_send(msgs::LocalPool(Arc::clone(pool)));
+        }
 
-        return Recipient::new(RecipientProxySender::new(saddr))
+        // notify new proxy about already known providers
+        if let Some(nodes) = self.types.get(M::type_id()) {
+            for node_id in nodes {
+                self.notify_proxy(&proxy, M::type_id(), node_id);
+            }
+        }
+        self.recipients.insert(M::type_id(), proxy);
+
+        (addr, saddr)
+    }
+
+    fn notify_proxy(&self, proxy: &Proxy, type_id: &str, node_id: &str) {
+        if let (Some(node), Some(info)) = (self.nodes.get(node_id), self.addrs.get(node_id)) {
+            let _ = proxy.service.do_send(
+                msgs::TypeSupported {
+                    type_id: type_id.to_string(),
+                    node_id: node_id.to_string(),
+                    info: info.clone(),
+                    node: node.clone(),
+                });
+        }
     }
 
     /// Register remote recipient provider.
     ///
     /// Announce recipient availability to all connected nodes.
+    /// Multiple recipients of same message type form a pool,
+    /// messages are dispatched according to `World::dispatch()` strategy.
     pub fn register_recipient<M>(world: &Addr<Syn, World>, recipient: Recipient<Syn, M>)
         where M: RemoteMessage + 'static, M::Result: Send + Serialize + DeserializeOwned
     {
-        let r = Provider{recipient: recipient};
+        let r = Provider::new(recipient, world.clone());
         world.do_send(msgs::ProvideRecipient{
             type_id: M::type_id(), handler: Arc::new(r)})
     }
 
+    /// Register actor as remote recipient provider.
+    ///
+    /// Same as `register_recipient()`, but world checks actor address
+    /// and unregisters provider shortly after actor stops.
+    pub fn register_actor<A, M>(world: &Addr<Syn, World>, addr: Addr<Syn, A>)
+        where A: Actor + Handler<M>, A::Context: ToEnvelope<Syn, A, M>,
+              M: RemoteMessage + 'static, M::Result: Send + Serialize + DeserializeOwned
+    {
+        let r = Provider::with_addr(addr, world.clone());
+        world.do_send(msgs::ProvideRecipient{
+            type_id: M::type_id(), handler: Arc::new(r)})
+    }
+
+    /// Unregister remote recipient provider.
+    ///
+    /// Withdraw recipient announcement from all connected nodes.
+    ///
+    /// `Recipient` can not tell if actor is stopped, so recipient registered
+    /// with `register_recipient()` is unregistered once delivery of next
+    /// message to it fails, that message fails with `RemoteError::NoProvider`.
+    /// Use `register_actor()` or call this method from actor's `stopping()`
+    /// to withdraw the announcement right away.
+    pub fn unregister_recipient<M>(world: &Addr<Syn, World>)
+        where M: RemoteMessage + 'static, M::Result: Send + Serialize + DeserializeOwned
+    {
+        world.do_send(msgs::WithdrawRecipient{type_id: M::type_id(), stopped: false})
+    }
+
+    /// Wait until at least one provider of message type is available
+    ///
+    /// Provider is counted only if local policy allows delivery to it:
+    /// local recipient is ignored with `LocalPolicy::RemoteOnly`, remote
+    /// nodes with `LocalPolicy::LocalOnly`. Future fails with
+    /// `RemoteError::Timeout` if no provider is available within `timeout`.
+    pub fn wait_for_provider<M>(world: &Addr<Syn, World>, timeout: Duration)
+                                -> Box<Future<Item=(), Error=RemoteError>>
+        where M: RemoteMessage + 'static, M::Result: Send + Serialize + DeserializeOwned
+    {
+        Box::new(
+            world.send(msgs::ClusterReady{
+                type_id: M::type_id().to_owned(), nodes: 1, timeout: timeout})
+                .then(|res| match res {
+                    Ok(res) => res,
+                    Err(err) => Err(RemoteError::from(err)),
+                }))
+    }
+
+    /// Number of providers of message type messages could be delivered to,
+    /// local recipients count as one provider
+    fn providers(&self, type_id: &str) -> usize {
+        let local = if self.handlers.contains_key(type_id) { 1 } else { 0 };
+        let remote = self.types.get(type_id).map(|nodes| nodes.len()).unwrap_or(0);
+        match self.config.local_policy {
+            LocalPolicy::LocalOnly => local,
+            LocalPolicy::LocalPreferred => local + remote,
+            LocalPolicy::RemoteOnly => remote,
+        }
+    }
+
+    /// Unregister local recipients of type, with `stopped` only
+    /// stopped recipients are removed from pool
+    fn withdraw(&mut self, type_id: &'static str, stopped: bool) {
+        // type is withdrawn only if all recipients are stopped
+        if stopped {
+            let pool = match self.handlers.get(type_id) {
+                Some(pool) => pool.without_closed(),
+                None => return,
+            };
+            if pool.len() != 0 {
+                if pool.len() != self.handlers[type_id].len() {
+                    self.set_pool(type_id, Arc::new(pool));
+                }
+                return
+            }
+        }
+
+        if self.handlers.remove(type_id).is_some() {
+            info!("Recipient for {} is unregistered", type_id);
+
+            if let Some(proxy) = self.recipients.get(type_id) {
+                let _ = proxy.local_gone.do_send(msgs::LocalRecipientGone);
+            }
+
+            // notify all workers
+            for addr in self.workers.values() {
+                addr.do_send(msgs::WithdrawRecipient{type_id: type_id, stopped: stopped});
+            }
+        }
+    }
+
+    /// Periodically unregister stopped actors
+    fn check_stopped(&mut self, ctx: &mut Context<Self>) {
+        let stopped: Vec<&'static str> = self.handlers.iter()
+            .filter(|&(_, pool)| pool.has_closed())
+            .map(|(type_id, _)| *type_id)
+            .collect();
+        for type_id in stopped {
+            warn!("Recipient of {} is stopped, unregistering", type_id);
+            self.withdraw(type_id, true);
+        }
+        ctx.run_later(Duration::from_secs(1), |act, ctx| act.check_stopped(ctx));
+    }
+
+    /// Resolve `ClusterReady` requests with enough providers
+    fn check_ready(&mut self) {
+        let ready: Vec<usize> = self.waiters.iter()
+            .filter(|&(_, waiter)| self.providers(&waiter.type_id) >= waiter.nodes)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in ready {
+            if let Some(waiter) = self.waiters.remove(&id) {
+                let _ = waiter.tx.send(Ok(()));
+            }
+        }
+    }
+
+    /// Update pool of local recipients, notify workers and recipient proxy
+    fn set_pool(&mut self, type_id: &'static str, pool: Arc<ProviderPool>) {
+        for addr in self.workers.values() {
+            addr.do_send(msgs::ProvideRecipient{type_id: type_id, handler: pool.clone()});
+        }
+
+        if let Some(proxy) = self.recipients.get(type_id) {
+            let _ = proxy.local.do_send(msgs::LocalPool(Arc::clone(&pool)));
+        }
+
+        self.handlers.insert(type_id, pool);
+        self.check_ready();
+    }
+
+    /// Local recipients for network workers
+    fn worker_handlers(&self) -> HashMap<&'static str, Arc<RemoteMessageHandler>> {
+        self.handlers.iter()
+            .map(|(type_id, pool)| (*type_id, Arc::clone(pool) as Arc<RemoteMessageHandler>))
+            .collect()
+    }
+
+    /// Start supervised network node
+    fn start_node(&mut self, addr: String, ctx: &mut Context<Self>) {
+        let naddr = self.addr.clone();
+        let net = ctx.address();
+        let info = self.addrs.entry(addr.clone())
+            .or_insert_with(|| NodeInformation::new(addr.clone())).clone();
+        let cfg = self.config.clone();
+        let node: Addr<Unsync, _> = Supervisor::start(
+            move |_| NetworkNode::new(naddr, net, info, cfg));
+        self.nodes.insert(addr, node);
+    }
+
+    /// Remove all providers of node
+    fn remove_node_providers(&mut self, node_id: &str) {
+        let types: Vec<String> = self.types.iter()
+            .filter(|&(_, nodes)| nodes.contains(node_id))
+            .map(|(tp, _)| tp.clone())
+            .collect();
+        for tp in types {
+            self.remove_provider(&tp, node_id);
+        }
+    }
+
+    /// Node does not provide type anymore, notify recipient proxy
+    fn remove_provider(&mut self, type_id: &str, node_id: &str) {
+        let empty = match self.types.get_mut(type_id) {
+            Some(nodes) => {
+                if !nodes.remove(node_id) {
+                    return
+                }
+                nodes.is_empty()
+            },
+            None => return,
+        };
+        if empty {
+            self.types.remove(type_id);
+        }
+
+        if let Some(proxy) = self.recipients.get(type_id) {
+            let _ = proxy.gone.do_send(msgs::NodeGone(node_id.to_string()));
+        }
+
+        self.emit(msgs::ClusterEvent::TypeWithdrawn{
+            type_id: type_id.to_string(), node: node_id.to_string()});
+    }
+
+    /// Send event to all subscribers, drop stopped subscribers
+    fn emit(&mut self, event: msgs::ClusterEvent) {
+        self.subscribers.retain(|subscriber| subscriber.do_send(event.clone()).is_ok());
+    }
+
+    /// Node is reachable through outbound or inbound connection
+    fn node_up(&mut self, node_id: &str) {
+        if self.up.insert(node_id.to_string()) {
+            self.emit(msgs::ClusterEvent::NodeUp(node_id.to_string()));
+        }
+    }
+
+    fn node_down(&mut self, node_id: &str) {
+        if self.up.remove(node_id) {
+            self.emit(msgs::ClusterEvent::NodeDown(node_id.to_string()));
+        }
+    }
+
     fn stop(&mut self, ctx: &mut Context<Self>) {
         if !self.exit {
             self.exit = true;
@@ -180,13 +577,9 @@ impl World {
                 ctx.add_stream(lst.incoming());
             }
 
-            for info in self.addrs.values() {
-                let net = ctx.address();
-                let info2 = info.clone();
-                let addr2 = self.addr.clone();
-                let node: Addr<Unsync, _> =
-                    Supervisor::start(move |_| NetworkNode::new(addr2, net, info2));
-                self.nodes.insert(info.address().to_string(), node);
+            let nodes: Vec<String> = self.addrs.keys().cloned().collect();
+            for addr in nodes {
+                self.start_node(addr, ctx);
             }
 
             self
@@ -199,12 +592,20 @@ impl Handler<msgs::ProvideRecipient> for World {
     type Result = ();
 
     fn handle(&mut self, msg: msgs::ProvideRecipient, _: &mut Self::Context) {
-        // notify all workers
-        for addr in self.workers.values() {
-            addr.do_send(msg.clone());
-        }
+        let pool = Arc::new(match self.handlers.get(msg.type_id) {
+            Some(pool) => pool.with(msg.handler),
+            None => ProviderPool::new(self.config.dispatch).with(msg.handler),
+        });
+        self.set_pool(msg.type_id, pool);
+    }
+}
 
-        self.handlers.insert(msg.type_id, msg.handler);
+/// Unregister remote message recipient
+impl Handler<msgs::WithdrawRecipient> for World {
+    type Result = ();
+
+    fn handle(&mut self, msg: msgs::WithdrawRecipient, _: &mut Self::Context) {
+        self.withdraw(msg.type_id, msg.stopped);
     }
 }
 
@@ -214,7 +615,7 @@ impl StreamHandler<(TcpStream, net::SocketAddr), io::Error> for World
     fn handle(&mut self, msg: (TcpStream, net::SocketAddr), ctx: &mut Context<Self>) {
         self.wid += 1;
         let addr = NetworkWorker::start(
-            self.wid, msg.0, self.handlers.clone(), ctx.address());
+            self.wid, msg.0, self.worker_handlers(), ctx.address(), self.config.clone());
         self.workers.insert(self.wid, addr);
     }
 }
@@ -225,6 +626,15 @@ impl Handler<msgs::WorkerDisconnected> for World {
 
     fn handle(&mut self, msg: msgs::WorkerDisconnected, _: &mut Self::Context) {
         self.workers.remove(&msg.0);
+
+        // node is still reachable while outbound connection is alive
+        if let Some(node_id) = self.peers.remove(&msg.0) {
+            let connected = self.addrs.get(&node_id)
+                .map(|info| info.status() == NodeStatus::Ok).unwrap_or(false);
+            if !connected {
+                self.node_down(&node_id);
+            }
+        }
     }
 }
 
@@ -233,18 +643,104 @@ impl Handler<msgs::NodeConnected> for World {
     type Result = ();
 
     fn handle(&mut self, msg: msgs::NodeConnected, ctx: &mut Context<Self>) {
-        if let Some(node) = self.nodes.get(&msg.0) {
+        if self.removed.contains(&msg.node) {
+            debug!("Network node {} is removed, not connecting", msg.node);
+            return
+        }
+        self.peers.insert(msg.worker, msg.node.clone());
+        self.node_up(&msg.node);
+
+        if let Some(node) = self.nodes.get(&msg.node) {
             node.do_send(msgs::ReconnectNode);
             return
         }
+        self.start_node(msg.node, ctx);
+    }
+}
 
-        let addr = msg.0.clone();
-        let naddr = self.addr.clone();
-        let net = ctx.address();
-        let info = NodeInformation::new(msg.0.clone());
-        let node: Addr<Unsync, _> =
-            Supervisor::start(move |_| NetworkNode::new(naddr, net, info));
-        self.nodes.insert(addr, node);
+/// Connection to remote node is established or lost
+impl Handler<msgs::NodeStatusChanged> for World {
+    type Result = ();
+
+    fn handle(&mut self, msg: msgs::NodeStatusChanged, _: &mut Context<Self>) {
+        if msg.status == NodeStatus::Ok {
+            if self.nodes.contains_key(&msg.node) {
+                self.node_up(&msg.node);
+            }
+        } else if !self.peers.values().any(|node_id| *node_id == msg.node) {
+            // node is still reachable while inbound connection is alive
+            self.node_down(&msg.node);
+        }
+    }
+}
+
+/// Subscribe to cluster events
+impl Handler<msgs::SubscribeCluster> for World {
+    type Result = ();
+
+    fn handle(&mut self, msg: msgs::SubscribeCluster, _: &mut Context<Self>) {
+        let subscriber = msg.0;
+
+        // current state
+        for node_id in &self.up {
+            let _ = subscriber.do_send(msgs::ClusterEvent::NodeUp(node_id.clone()));
+        }
+        for (tp, nodes) in &self.types {
+            for node_id in nodes {
+                let _ = subscriber.do_send(msgs::ClusterEvent::TypeAvailable{
+                    type_id: tp.clone(), node: node_id.clone()});
+            }
+        }
+        self.subscribers.push(subscriber);
+    }
+}
+
+/// Connect to network node
+impl Handler<msgs::AddNode> for World {
+    type Result = ();
+
+    fn handle(&mut self, msg: msgs::AddNode, ctx: &mut Context<Self>) {
+        self.removed.remove(&msg.0);
+        if !self.nodes.contains_key(&msg.0) {
+            info!("Adding network node {}", msg.0);
+            self.start_node(msg.0, ctx);
+        }
+    }
+}
+
+/// Disconnect from network node, remove all its providers
+impl Handler<msgs::RemoveNode> for World {
+    type Result = ();
+
+    fn handle(&mut self, msg: msgs::RemoveNode, _: &mut Context<Self>) {
+        self.removed.insert(msg.0.clone());
+        self.addrs.remove(&msg.0);
+        if let Some(node) = self.nodes.remove(&msg.0) {
+            info!("Removing network node {}", msg.0);
+            node.do_send(msgs::StopNode);
+        }
+        self.remove_node_providers(&msg.0);
+        self.node_down(&msg.0);
+    }
+}
+
+/// Remote node failed permanently, remove all its providers
+impl Handler<msgs::NodeFailed> for World {
+    type Result = ();
+
+    fn handle(&mut self, msg: msgs::NodeFailed, _: &mut Context<Self>) {
+        self.remove_node_providers(&msg.0);
+    }
+}
+
+/// Remote node withdrew recipients
+impl Handler<msgs::NodeUnsupportedTypes> for World {
+    type Result = ();
+
+    fn handle(&mut self, msg: msgs::NodeUnsupportedTypes, _: &mut Context<Self>) {
+        for tp in &msg.types {
+            self.remove_provider(tp, &msg.node);
+        }
     }
 }
 
@@ -255,27 +751,82 @@ impl Handler<msgs::NodeSupportedTypes> for World {
     type Result = ();
 
     fn handle(&mut self, msg: msgs::NodeSupportedTypes, _: &mut Context<Self>) {
+        // announcement from node that has just been removed
+        if self.removed.contains(&msg.node) {
+            return
+        }
+
         // register in internal registry
+        let mut added = Vec::new();
         for tp in &msg.types {
             if !self.types.contains_key(tp) {
                 self.types.insert(tp.clone(), HashSet::new());
             }
-            self.types.get_mut(tp).unwrap().insert(msg.node.clone());
+            if self.types.get_mut(tp).unwrap().insert(msg.node.clone()) {
+                added.push(tp.clone());
+            }
+        }
+        for tp in added {
+            self.emit(msgs::ClusterEvent::TypeAvailable{type_id: tp, node: msg.node.clone()});
         }
 
         // notify all recipient proxies
-        if let Some(node) = self.nodes.get(&msg.node) {
-            for tp in msg.types {
-                if let Some(proxy) = self.recipients.get(tp.as_str()) {
-                    let _ = proxy.service.do_send(
-                        msgs::TypeSupported {
-                            type_id: tp,
-                            node_id: msg.node.clone(),
-                            node: node.clone(),
-                        });
-                }
+        for tp in &msg.types {
+            if let Some(proxy) = self.recipients.get(tp.as_str()) {
+                self.notify_proxy(proxy, tp, &msg.node);
             }
         }
+
+        self.check_ready();
+    }
+}
+
+/// Snapshot of nodes, workers and providers
+impl Handler<msgs::GetClusterState> for World {
+    type Result = MessageResult<msgs::GetClusterState>;
+
+    fn handle(&mut self, _: msgs::GetClusterState, _: &mut Context<Self>) -> Self::Result {
+        MessageResult(msgs::ClusterState{
+            nodes: self.addrs.iter()
+                .map(|(addr, info)| (addr.clone(), info.status()))
+                .collect(),
+            workers: self.workers.keys()
+                .map(|id| (*id, self.peers.get(id).cloned()))
+                .collect(),
+            types: self.types.iter()
+                .map(|(tp, nodes)| (tp.clone(), nodes.iter().cloned().collect()))
+                .collect(),
+            handlers: self.handlers.iter()
+                .map(|(tp, pool)| (tp.to_string(), pool.len()))
+                .collect(),
+        })
+    }
+}
+
+/// Wait until enough nodes provide message type
+impl Handler<msgs::ClusterReady> for World {
+    type Result = ActixResponse<(), RemoteError>;
+
+    fn handle(&mut self, msg: msgs::ClusterReady, ctx: &mut Context<Self>) -> Self::Result {
+        if self.providers(&msg.type_id) >= msg.nodes {
+            return ActixResponse::reply(Ok(()))
+        }
+
+        let (tx, rx) = oneshot::channel();
+        self.wait_id += 1;
+        let id = self.wait_id;
+        self.waiters.insert(
+            id, ReadyWaiter{type_id: msg.type_id, nodes: msg.nodes, tx: tx});
+        ctx.run_later(msg.timeout, move |act, _| {
+            if let Some(waiter) = act.waiters.remove(&id) {
+                let _ = waiter.tx.send(Err(RemoteError::Timeout));
+            }
+        });
+
+        ActixResponse::async(rx.then(|res| match res {
+            Ok(res) => res,
+            Err(_) => Err(RemoteError::Closed),
+        }))
     }
 }
 